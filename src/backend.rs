@@ -0,0 +1,574 @@
+//! Backends that run one binary on a target.
+//!
+//! Every backend goes through the same steps: start a server, flash the binary,
+//! set up the transport of defmt frames, run the target, and tear everything down.
+
+use std::{
+    future::Future,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    time::Duration,
+};
+
+use tokio::{io::AsyncRead, net::TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cfg::{BackendKind, CfgError, ResolvedConfig, RunnerConfig},
+    gdb::{GdbMi, MiError, StopReason},
+    process::Registration,
+    RunnerError,
+};
+
+pub mod gdb_server;
+pub mod jlink;
+pub mod openocd;
+pub mod openocd_server;
+pub mod pyocd;
+pub mod qemu;
+
+/// Steps to run one binary on a target.
+///
+/// The steps are called in order.
+/// `teardown()` is always called at the end, even if a previous step failed.
+pub trait Backend: Send {
+    /// Stream the defmt frames of the target are read from.
+    type LogStream: AsyncRead + Unpin + Send + 'static;
+
+    /// Starts the GDB server or emulator, if the backend manages one.
+    fn start_server(&mut self) -> impl Future<Output = Result<(), RunnerError>> + Send;
+
+    /// Connects to the target, loads the binary, and halts the target at `main`.
+    fn flash(&mut self) -> impl Future<Output = Result<(), RunnerError>> + Send;
+
+    /// Sets up the transport of defmt frames, and connects to it.
+    fn setup_log_transport(
+        &mut self,
+    ) -> impl Future<Output = Result<Self::LogStream, RunnerError>> + Send;
+
+    /// Lets the target run until it stops on its own, or until `stop` is cancelled.
+    fn run(
+        &mut self,
+        stop: CancellationToken,
+    ) -> impl Future<Output = Result<TargetStop, RunnerError>> + Send;
+
+    /// Shuts down everything the backend started.
+    fn teardown(self) -> impl Future<Output = Result<(), RunnerError>> + Send;
+}
+
+/// Ways the target may stop running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetStop {
+    /// The target stopped on its own (e.g. on a breakpoint, or because the program exited).
+    Stopped,
+    /// The target stopped in the HardFault handler.
+    HardFault,
+    /// The target was halted, because `stop` was cancelled.
+    Halted,
+    /// `stop` was cancelled, but the target could not be halted.
+    NotHalted,
+}
+
+/// Backends that may be selected in the runner configuration.
+pub enum ConfiguredBackend {
+    OpenOcd(openocd::OpenOcdBackend),
+    OpenOcdServer(openocd_server::OpenOcdServerBackend),
+    GdbServer(gdb_server::GdbServerBackend),
+    Segger(gdb_server::SeggerBackend),
+    JLink(Box<jlink::JLinkBackend>),
+    Qemu(Box<qemu::QemuBackend>),
+    PyOcd(Box<pyocd::PyOcdBackend>),
+}
+
+impl ConfiguredBackend {
+    /// Creates the backend set in the runner configuration.
+    pub fn new(
+        main_cfg: &ResolvedConfig,
+        binary: &Path,
+        output_dir: &Path,
+        segger_gdb: bool,
+    ) -> Result<Self, RunnerError> {
+        let runner_cfg = &main_cfg.runner_cfg;
+        let workspace_dir = &main_cfg.workspace_dir;
+
+        let backend = match (runner_cfg.backend, &runner_cfg.gdb_connection) {
+            (BackendKind::Qemu, _) => ConfiguredBackend::Qemu(Box::new(qemu::QemuBackend::new(
+                runner_cfg,
+                binary,
+                workspace_dir,
+                output_dir,
+            )?)),
+            (BackendKind::PyOcd, _) => ConfiguredBackend::PyOcd(Box::new(
+                pyocd::PyOcdBackend::new(runner_cfg, binary, workspace_dir, output_dir)?,
+            )),
+            (BackendKind::OpenOcd, Some(gdb_connection)) if segger_gdb => {
+                ConfiguredBackend::Segger(gdb_server::SeggerBackend::new(
+                    runner_cfg,
+                    gdb_connection,
+                    binary,
+                    workspace_dir,
+                    output_dir,
+                )?)
+            }
+            (BackendKind::OpenOcd, Some(gdb_connection)) => {
+                ConfiguredBackend::GdbServer(gdb_server::GdbServerBackend::new(
+                    runner_cfg,
+                    gdb_connection,
+                    binary,
+                    workspace_dir,
+                    output_dir,
+                )?)
+            }
+            (BackendKind::OpenOcd, None) if segger_gdb => match &runner_cfg.jlink {
+                Some(jlink_cfg) => ConfiguredBackend::JLink(Box::new(jlink::JLinkBackend::new(
+                    runner_cfg,
+                    jlink_cfg,
+                    binary,
+                    workspace_dir,
+                    output_dir,
+                )?)),
+                None => return Err(cfg_error(CfgError::MissingGdbConnection)),
+            },
+            (BackendKind::OpenOcd, None) => match &runner_cfg.openocd_server {
+                Some(server_cfg) => {
+                    ConfiguredBackend::OpenOcdServer(openocd_server::OpenOcdServerBackend::new(
+                        main_cfg, server_cfg, binary, output_dir,
+                    )?)
+                }
+                None => ConfiguredBackend::OpenOcd(openocd::OpenOcdBackend::new(
+                    runner_cfg,
+                    binary,
+                    workspace_dir,
+                    output_dir,
+                )?),
+            },
+        };
+
+        Ok(backend)
+    }
+}
+
+/// Delay between checks if a server started by the runner is ready.
+const SERVER_READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// GDB server started by the runner for one run.
+#[derive(Debug)]
+pub struct ServerProcess {
+    /// Name of the server used in messages.
+    name: &'static str,
+    child: tokio::process::Child,
+    _registration: Registration,
+    logfile: PathBuf,
+}
+
+impl ServerProcess {
+    /// Starts the server with the given arguments.
+    ///
+    /// Output of the server is written to the given logfile.
+    pub fn spawn(
+        name: &'static str,
+        executable: &str,
+        args: &[String],
+        workspace_dir: &Path,
+        logfile: &Path,
+    ) -> Result<Self, RunnerError> {
+        log::debug!("Starting {name}: {executable} {}", args.join(" "));
+
+        let spawn = || -> std::io::Result<(tokio::process::Child, Registration)> {
+            let log = std::fs::File::create(logfile)?;
+
+            let mut cmd = tokio::process::Command::new(executable);
+            cmd.args(args)
+                .current_dir(workspace_dir)
+                .stdin(Stdio::null())
+                .stdout(log.try_clone()?)
+                .stderr(log)
+                .kill_on_drop(true);
+
+            crate::process::spawn(&mut cmd)
+        };
+
+        let (child, registration) = spawn().map_err(|err| {
+            RunnerError::Connection(format!("Could not start {name}. Cause: {err}"))
+        })?;
+
+        Ok(Self {
+            name,
+            child,
+            _registration: registration,
+            logfile: logfile.to_path_buf(),
+        })
+    }
+
+    /// Waits until the server logs one of the given messages.
+    ///
+    /// The log is checked instead of connecting to the server,
+    /// because GDB servers may only accept one GDB connection.
+    /// Messages must be lowercase.
+    pub async fn wait_until_ready(&mut self, ready_messages: &[&str]) -> Result<(), RunnerError> {
+        loop {
+            let log = tokio::fs::read_to_string(&self.logfile)
+                .await
+                .unwrap_or_default()
+                .to_lowercase();
+            if ready_messages.iter().any(|msg| log.contains(msg)) {
+                return Ok(());
+            }
+
+            if let Ok(Some(status)) = self.child.try_wait() {
+                return Err(target_error(
+                    format!(
+                        "{} exited with '{status}'. See '{}'.",
+                        self.name,
+                        self.logfile.display()
+                    ),
+                    Some(&self.logfile),
+                ));
+            }
+
+            tokio::time::sleep(SERVER_READY_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Stops the server.
+    pub async fn kill(mut self) -> std::io::Result<ExitStatus> {
+        self.child.kill().await?;
+        self.child.wait().await
+    }
+}
+
+/// Maps errors in the runner configuration that are detected while creating a backend.
+pub(crate) fn cfg_error(err: CfgError) -> RunnerError {
+    RunnerError::GdbCommands(err.to_string())
+}
+
+/// GDB commands for the steps of one test run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GdbCommands {
+    /// Commands to connect GDB with the target.
+    pub connection: Vec<String>,
+    /// Logfile of the GDB server that is started by GDB.
+    pub server_log: Option<PathBuf>,
+    /// Commands to load the binary onto the target.
+    pub load: Vec<String>,
+    /// Commands to start the RTT server.
+    pub rtt: Vec<String>,
+    /// Commands executed after the run, before GDB quits.
+    pub pre_exit: Vec<String>,
+}
+
+/// Maximum duration GDB may take to stop the target or to exit.
+const GDB_INTERRUPT_TIMEOUT: Duration = Duration::from_secs(10);
+/// First delay before retrying to connect to the RTT server.
+const RTT_CONNECT_MIN_BACKOFF: Duration = Duration::from_millis(20);
+/// Maximum delay between connection attempts to the RTT server.
+const RTT_CONNECT_MAX_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Messages of GDB servers signaling that no debug probe was found.
+const NO_DEVICE_MESSAGES: [&str; 7] = [
+    "no device found",
+    "unable to find",
+    "open failed",
+    "no j-link",
+    "no probe",
+    "no connected debug probes",
+    "cannot connect to j-link",
+];
+
+/// GDB controlling the target for backends that use a GDB server.
+pub struct GdbSession {
+    gdb: GdbMi,
+    commands: GdbCommands,
+    /// Number of the breakpoint set at the HardFault handler if the binary has one.
+    hard_fault_bkpt: Option<String>,
+    /// `true`: The target was halted after running => GDB may exit gracefully.
+    halted: bool,
+}
+
+impl GdbSession {
+    /// Starts the GDB resolved for the given binary (see [`RunnerConfig::gdb_executable()`]).
+    pub fn spawn(
+        runner_cfg: &RunnerConfig,
+        binary: &Path,
+        workspace_dir: &Path,
+        commands: GdbCommands,
+    ) -> Result<Self, RunnerError> {
+        let gdb_exe = runner_cfg.gdb_executable(binary);
+        log::debug!("Using GDB '{gdb_exe}'.");
+        let gdb = GdbMi::spawn(&gdb_exe, runner_cfg.gdb_args(), binary, workspace_dir)
+            .map_err(|err| RunnerError::Gdb(err.to_string()))?;
+
+        Ok(Self {
+            gdb,
+            commands,
+            hard_fault_bkpt: None,
+            halted: false,
+        })
+    }
+
+    /// Connects to the target, loads the binary, and runs it until `main`.
+    pub async fn flash(&mut self) -> Result<(), RunnerError> {
+        let gdb = &mut self.gdb;
+
+        gdb.execute("-gdb-set pagination off")
+            .await
+            .map_err(gdb_error)?;
+        gdb.execute("-gdb-set confirm off")
+            .await
+            .map_err(gdb_error)?;
+
+        for cmd in &self.commands.connection {
+            gdb.console(cmd)
+                .await
+                .map_err(|err| connection_error(err, self.commands.server_log.as_deref()))?;
+        }
+
+        for cmd in &self.commands.load {
+            gdb.console(cmd).await.map_err(|err| match err {
+                MiError::Command(msg) => RunnerError::Load(msg),
+                err => gdb_error(err),
+            })?;
+        }
+
+        let main_bkpt = gdb.insert_breakpoint("main").await.map_err(gdb_error)?;
+        self.hard_fault_bkpt = gdb.insert_breakpoint("HardFault").await.ok();
+        // the target may report stops while connecting or loading
+        gdb.clear_stops();
+        gdb.execute("-exec-continue").await.map_err(gdb_error)?;
+
+        match gdb.wait_for_stop().await.map_err(gdb_error)? {
+            StopReason::BreakpointHit(bkpt) if bkpt == main_bkpt => Ok(()),
+            reason => Err(RunnerError::Gdb(format!(
+                "Target stopped before reaching `main`. Reason: {reason:?}"
+            ))),
+        }
+    }
+
+    /// Starts the RTT server of the GDB server.
+    pub async fn start_rtt(&mut self) -> Result<(), RunnerError> {
+        for cmd in &self.commands.rtt {
+            self.gdb
+                .console(cmd)
+                .await
+                .map_err(|err| RunnerError::Rtt(err.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Continues the target until it stops, or halts it once `stop` is cancelled.
+    pub async fn run(&mut self, stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+        self.halted = false;
+        self.gdb.clear_stops();
+        self.gdb
+            .execute("-exec-continue")
+            .await
+            .map_err(gdb_error)?;
+
+        let reason = tokio::select! {
+            reason = self.gdb.wait_for_stop() => Some(reason.map_err(gdb_error)?),
+            _ = stop.cancelled() => None,
+        };
+
+        let Some(reason) = reason else {
+            self.gdb
+                .execute("-exec-interrupt")
+                .await
+                .map_err(gdb_error)?;
+
+            return match tokio::time::timeout(GDB_INTERRUPT_TIMEOUT, self.gdb.wait_for_stop()).await
+            {
+                Ok(reason) => {
+                    reason.map_err(gdb_error)?;
+                    self.halted = true;
+                    Ok(TargetStop::Halted)
+                }
+                Err(_) => Ok(TargetStop::NotHalted),
+            };
+        };
+
+        self.halted = true;
+
+        match &reason {
+            StopReason::SignalReceived(signal) => {
+                log::debug!("Target stopped on signal '{signal}'.");
+                Ok(TargetStop::Stopped)
+            }
+            StopReason::BreakpointHit(bkpt) if Some(bkpt) == self.hard_fault_bkpt.as_ref() => {
+                Ok(TargetStop::HardFault)
+            }
+            _ => Ok(TargetStop::Stopped),
+        }
+    }
+
+    /// Executes the pre-exit commands, and lets GDB exit if the target is halted.
+    ///
+    /// GDB is killed if the target is still running.
+    pub async fn teardown(mut self) -> Result<(), RunnerError> {
+        if !self.halted {
+            let _ = self.gdb.kill().await;
+            return Ok(());
+        }
+
+        for cmd in &self.commands.pre_exit {
+            if let Err(err) = self.gdb.console(cmd).await {
+                log::error!("Pre-exit command '{cmd}' failed. Cause: {err}");
+            }
+        }
+
+        match self.gdb.exit(GDB_INTERRUPT_TIMEOUT).await {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(RunnerError::Gdb(format!(
+                "GDB did not run successfully. Exit code: '{status}'"
+            ))),
+            Err(err) => Err(RunnerError::Gdb(format!(
+                "Error waiting for gdb to finish. Cause: {err}"
+            ))),
+        }
+    }
+}
+
+fn gdb_error(err: MiError) -> RunnerError {
+    RunnerError::Gdb(err.to_string())
+}
+
+/// Maps errors while connecting to the target.
+///
+/// The log of the GDB server is checked, because the error from GDB only states that the connection failed.
+fn connection_error(err: MiError, server_log: Option<&Path>) -> RunnerError {
+    match err {
+        MiError::Command(msg) => target_error(msg, server_log),
+        err => gdb_error(err),
+    }
+}
+
+/// Maps errors of the GDB server to [`RunnerError::NoDevice`] if no debug probe was found,
+/// and to [`RunnerError::Connection`] otherwise.
+fn target_error(msg: String, server_log: Option<&Path>) -> RunnerError {
+    let server_log = server_log
+        .and_then(|log| std::fs::read_to_string(log).ok())
+        .unwrap_or_default();
+    let no_device = [msg.as_str(), server_log.as_str()].iter().any(|output| {
+        let output = output.to_lowercase();
+        NO_DEVICE_MESSAGES
+            .iter()
+            .any(|no_device_msg| output.contains(no_device_msg))
+    });
+
+    if no_device {
+        RunnerError::NoDevice(msg)
+    } else {
+        RunnerError::Connection(msg)
+    }
+}
+
+/// Returns a free local port for a server started by the run.
+///
+/// The port is freed again, so the server may bind it.
+pub(crate) fn free_local_port() -> Result<u16, RunnerError> {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|err| RunnerError::Rtt(format!("Could not find a free RTT port. Cause: {err}")))
+}
+
+/// Connects to the local TCP server the defmt frames are sent to.
+///
+/// The connection is retried until the server is ready.
+pub async fn connect_log_socket(port: u16) -> Result<TcpStream, RunnerError> {
+    let mut backoff = RTT_CONNECT_MIN_BACKOFF;
+    loop {
+        match TcpStream::connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port)).await {
+            Ok(stream) => {
+                return Ok(stream);
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::TimedOut | std::io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                log::debug!(
+                    "RTT server not ready. Retrying in {}ms.",
+                    backoff.as_millis()
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RTT_CONNECT_MAX_BACKOFF);
+            }
+            Err(err) => {
+                return Err(RunnerError::Rtt(err.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::gdb::GdbMi;
+
+    use super::{GdbCommands, GdbSession};
+
+    /// Fake GDB that reports a stop while connecting, like GDB does for an already halted target.
+    #[cfg(unix)]
+    const FAKE_GDB: &str = r#"#!/bin/sh
+while read -r line; do
+    token=${line%%[!0-9]*}
+    case "$line" in
+        *target*)
+            echo '*stopped,reason="signal-received",signal-name="SIGINT"'
+            echo "${token}^done"
+            ;;
+        *"-break-insert main"*) echo "${token}^done,bkpt={number=\"1\"}" ;;
+        *-break-insert*) echo "${token}^error,msg=\"No symbol.\"" ;;
+        *-exec-continue*)
+            echo "${token}^running"
+            echo '*stopped,reason="breakpoint-hit",bkptno="1"'
+            ;;
+        *) echo "${token}^done" ;;
+    esac
+done
+"#;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stop_while_connecting_is_ignored() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("emb-runner-fake-gdb");
+        std::fs::create_dir_all(&dir).unwrap();
+        let gdb_path = dir.join("gdb");
+        std::fs::write(&gdb_path, FAKE_GDB).unwrap();
+        std::fs::set_permissions(&gdb_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let gdb = GdbMi::spawn(
+            &gdb_path.display().to_string(),
+            &[],
+            &PathBuf::from("firmware.elf"),
+            &dir,
+        )
+        .unwrap();
+        let mut session = GdbSession {
+            gdb,
+            commands: GdbCommands {
+                connection: vec!["target extended-remote localhost:3333".to_string()],
+                server_log: None,
+                load: vec!["load".to_string()],
+                rtt: Vec::new(),
+                pre_exit: Vec::new(),
+            },
+            hard_fault_bkpt: None,
+            halted: false,
+        };
+
+        let flashed = session.flash().await;
+        let _ = session.gdb.kill().await;
+
+        assert!(
+            flashed.is_ok(),
+            "Stop while connecting taken for the stop at `main`: {flashed:?}"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cfg::{find_rtt_block, CfgError, RttPort, RunnerConfig},
+    RunnerError,
+};
+
+use super::{
+    cfg_error, connect_log_socket, openocd::RttServer, Backend, GdbCommands, GdbSession, TargetStop,
+};
+
+/// GDB server that is started outside the runner, and supports the `monitor rtt` commands of OpenOCD.
+pub struct GdbServerBackend {
+    session: GdbSession,
+    rtt_server: RttServer,
+}
+
+impl GdbServerBackend {
+    pub fn new(
+        runner_cfg: &RunnerConfig,
+        gdb_connection: &str,
+        binary: &Path,
+        workspace_dir: &Path,
+        output_dir: &Path,
+    ) -> Result<Self, RunnerError> {
+        let rtt_server = RttServer::new(runner_cfg.rtt_server_port())?;
+        let commands = GdbCommands {
+            rtt: super::openocd::rtt_commands(binary, &rtt_server)?,
+            ..extern_server_commands(runner_cfg, gdb_connection, binary, output_dir)?
+        };
+
+        Ok(Self {
+            session: GdbSession::spawn(runner_cfg, binary, workspace_dir, commands)?,
+            rtt_server,
+        })
+    }
+}
+
+impl Backend for GdbServerBackend {
+    type LogStream = TcpStream;
+
+    async fn start_server(&mut self) -> Result<(), RunnerError> {
+        // server is started outside the runner
+        Ok(())
+    }
+
+    async fn flash(&mut self) -> Result<(), RunnerError> {
+        self.session.flash().await
+    }
+
+    async fn setup_log_transport(&mut self) -> Result<TcpStream, RunnerError> {
+        self.session.start_rtt().await?;
+        self.rtt_server.connect().await
+    }
+
+    async fn run(&mut self, stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+        self.session.run(stop).await
+    }
+
+    async fn teardown(self) -> Result<(), RunnerError> {
+        self.session.teardown().await
+    }
+}
+
+/// SEGGER J-Link GDB server that is started outside the runner.
+///
+/// The server provides RTT data on its RTT telnet port.
+pub struct SeggerBackend {
+    session: GdbSession,
+    rtt_port: u16,
+}
+
+impl SeggerBackend {
+    pub fn new(
+        runner_cfg: &RunnerConfig,
+        gdb_connection: &str,
+        binary: &Path,
+        workspace_dir: &Path,
+        output_dir: &Path,
+    ) -> Result<Self, RunnerError> {
+        // SEGGER GDB server provides RTT data on its own telnet port
+        let RttPort::Fixed(rtt_port) = runner_cfg.rtt_server_port() else {
+            return Err(cfg_error(CfgError::AutoRttPortUnsupported));
+        };
+        let commands = GdbCommands {
+            rtt: segger_rtt_commands(binary)?,
+            ..extern_server_commands(runner_cfg, gdb_connection, binary, output_dir)?
+        };
+
+        Ok(Self {
+            session: GdbSession::spawn(runner_cfg, binary, workspace_dir, commands)?,
+            rtt_port,
+        })
+    }
+}
+
+impl Backend for SeggerBackend {
+    type LogStream = TcpStream;
+
+    async fn start_server(&mut self) -> Result<(), RunnerError> {
+        // server is started outside the runner
+        Ok(())
+    }
+
+    async fn flash(&mut self) -> Result<(), RunnerError> {
+        self.session.flash().await
+    }
+
+    async fn setup_log_transport(&mut self) -> Result<TcpStream, RunnerError> {
+        self.session.start_rtt().await?;
+        connect_log_socket(self.rtt_port).await
+    }
+
+    async fn run(&mut self, stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+        self.session.run(stop).await
+    }
+
+    async fn teardown(self) -> Result<(), RunnerError> {
+        self.session.teardown().await
+    }
+}
+
+/// Commands of the SEGGER GDB server to find the RTT control block of the binary.
+pub(crate) fn segger_rtt_commands(binary: &Path) -> Result<Vec<String>, RunnerError> {
+    let (rtt_address, rtt_length) = find_rtt_block(binary).map_err(cfg_error)?;
+
+    Ok(vec![
+        format!("monitor exec SetRTTSearchRanges 0x{rtt_address:x} 0x{rtt_length:x}"),
+        "monitor exec SetRTTChannel 0".to_string(),
+    ])
+}
+
+/// GDB commands to run the binary using the GDB server at `gdb_connection`, without RTT commands.
+fn extern_server_commands(
+    runner_cfg: &RunnerConfig,
+    gdb_connection: &str,
+    binary: &Path,
+    output_dir: &Path,
+) -> Result<GdbCommands, RunnerError> {
+    if let Some(probe_serial) = &runner_cfg.probe_serial {
+        log::warn!(
+            "Probe serial '{probe_serial}' is not passed to the GDB server at '{gdb_connection}', because it is started outside the runner. Select the probe when starting the server."
+        );
+    }
+
+    Ok(GdbCommands {
+        connection: vec![
+            format!("target extended-remote {gdb_connection}"),
+            format!("set logging file {}", runner_cfg.gdb_logfile(output_dir)),
+        ],
+        server_log: None,
+        load: runner_cfg
+            .load_commands(binary, "load")
+            .map_err(cfg_error)?,
+        rtt: Vec::new(),
+        pre_exit: runner_cfg.pre_exit_commands(binary).map_err(cfg_error)?,
+    })
+}
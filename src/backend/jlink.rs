@@ -0,0 +1,178 @@
+//! SEGGER J-Link GDB server started by the runner for every run.
+
+use std::path::{Path, PathBuf};
+
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cfg::{JLinkConfig, RttPort, RunnerConfig},
+    RunnerError,
+};
+
+use super::{
+    cfg_error, connect_log_socket, free_local_port, gdb_server::segger_rtt_commands, Backend,
+    GdbCommands, GdbSession, ServerProcess, TargetStop,
+};
+
+/// Default J-Link GDB server executable.
+#[cfg(not(windows))]
+pub const DEFAULT_JLINK: &str = "JLinkGDBServerCLExe";
+#[cfg(windows)]
+pub const DEFAULT_JLINK: &str = "JLinkGDBServerCL";
+
+/// Message the J-Link GDB server logs once it accepts connections.
+const READY_MESSAGE: &str = "waiting for gdb connection";
+
+/// SEGGER J-Link GDB server started for the run, and controlled by GDB.
+///
+/// The server provides RTT data on its RTT telnet port.
+pub struct JLinkBackend {
+    cfg: JLinkConfig,
+    args: Vec<String>,
+    workspace_dir: PathBuf,
+    logfile: PathBuf,
+    rtt_port: u16,
+    server: Option<ServerProcess>,
+    session: GdbSession,
+}
+
+impl JLinkBackend {
+    pub fn new(
+        runner_cfg: &RunnerConfig,
+        cfg: &JLinkConfig,
+        binary: &Path,
+        workspace_dir: &Path,
+        output_dir: &Path,
+    ) -> Result<Self, RunnerError> {
+        // server is started for this run only, so the picked port needs no greeting
+        let rtt_port = match runner_cfg.rtt_server_port() {
+            RttPort::Fixed(port) => port,
+            RttPort::Auto => free_local_port()?,
+        };
+        let logfile = output_dir.join("jlink.log");
+
+        let commands = GdbCommands {
+            connection: vec![
+                format!("target extended-remote localhost:{}", cfg.gdb_port()),
+                format!("set logging file {}", runner_cfg.gdb_logfile(output_dir)),
+            ],
+            server_log: Some(logfile.clone()),
+            load: runner_cfg
+                .load_commands(binary, "load")
+                .map_err(cfg_error)?,
+            rtt: segger_rtt_commands(binary)?,
+            pre_exit: runner_cfg.pre_exit_commands(binary).map_err(cfg_error)?,
+        };
+
+        Ok(Self {
+            args: args(cfg, runner_cfg.probe_serial.as_deref(), rtt_port),
+            cfg: cfg.clone(),
+            workspace_dir: workspace_dir.to_path_buf(),
+            logfile,
+            rtt_port,
+            server: None,
+            session: GdbSession::spawn(runner_cfg, binary, workspace_dir, commands)?,
+        })
+    }
+}
+
+impl Backend for JLinkBackend {
+    type LogStream = TcpStream;
+
+    async fn start_server(&mut self) -> Result<(), RunnerError> {
+        let server = ServerProcess::spawn(
+            "J-Link GDB server",
+            self.cfg.executable.as_deref().unwrap_or(DEFAULT_JLINK),
+            &self.args,
+            &self.workspace_dir,
+            &self.logfile,
+        )?;
+
+        // stored before waiting, so the server is killed in `teardown()` if it never gets ready
+        self.server
+            .insert(server)
+            .wait_until_ready(&[READY_MESSAGE])
+            .await
+    }
+
+    async fn flash(&mut self) -> Result<(), RunnerError> {
+        self.session.flash().await
+    }
+
+    async fn setup_log_transport(&mut self) -> Result<TcpStream, RunnerError> {
+        self.session.start_rtt().await?;
+        connect_log_socket(self.rtt_port).await
+    }
+
+    async fn run(&mut self, stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+        self.session.run(stop).await
+    }
+
+    async fn teardown(self) -> Result<(), RunnerError> {
+        let result = self.session.teardown().await;
+
+        if let Some(server) = self.server {
+            let _ = server.kill().await;
+        }
+
+        result
+    }
+}
+
+/// Arguments to start the J-Link GDB server.
+pub fn args(cfg: &JLinkConfig, probe_serial: Option<&str>, rtt_port: u16) -> Vec<String> {
+    let mut args = vec![
+        "-device".to_string(),
+        cfg.device.clone(),
+        "-if".to_string(),
+        cfg.interface.clone().unwrap_or("SWD".to_string()),
+        "-speed".to_string(),
+        cfg.speed.unwrap_or(4000).to_string(),
+    ];
+
+    if let Some(serial) = probe_serial {
+        args.push("-select".to_string());
+        args.push(format!("USB={serial}"));
+    }
+
+    args.extend([
+        "-port".to_string(),
+        cfg.gdb_port().to_string(),
+        "-swoport".to_string(),
+        cfg.swo_port().to_string(),
+        "-telnetport".to_string(),
+        cfg.telnet_port().to_string(),
+        "-RTTTelnetPort".to_string(),
+        rtt_port.to_string(),
+        "-nogui".to_string(),
+        "-singlerun".to_string(),
+    ]);
+    args.extend(cfg.args.iter().cloned());
+
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cfg::JLinkConfig;
+
+    #[test]
+    fn server_for_probe_and_rtt_port() {
+        let cfg: JLinkConfig = toml::from_str(
+            "
+            device = \"STM32F407VG\"
+            speed = 12000
+            args = [\"-noir\"]
+            ",
+        )
+        .unwrap();
+
+        let args = super::args(&cfg, Some("000123456789"), 19021).join(" ");
+
+        assert_eq!(
+            args,
+            "-device STM32F407VG -if SWD -speed 12000 -select USB=000123456789 -port 2331 -swoport 2332 -telnetport 2333 -RTTTelnetPort 19021 -nogui -singlerun -noir"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use tokio::{io::AsyncReadExt, net::TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cfg::{find_rtt_block, RttPort, RunnerConfig},
+    RunnerError,
+};
+
+use super::{
+    cfg_error, connect_log_socket, free_local_port, Backend, GdbCommands, GdbSession, TargetStop,
+};
+
+/// OpenOCD started by GDB over a pipe.
+pub struct OpenOcdBackend {
+    session: GdbSession,
+    rtt_server: RttServer,
+}
+
+impl OpenOcdBackend {
+    pub fn new(
+        runner_cfg: &RunnerConfig,
+        binary: &Path,
+        workspace_dir: &Path,
+        output_dir: &Path,
+    ) -> Result<Self, RunnerError> {
+        let gdb_logfile = runner_cfg.gdb_logfile(output_dir);
+        let openocd = runner_cfg.openocd_command(binary).map_err(cfg_error)?;
+        let rtt_server = RttServer::new(runner_cfg.rtt_server_port())?;
+
+        // Tcl and telnet servers are not used, and would block runs on other probes
+        let mut openocd_cmds = format!(
+            "gdb_port pipe; tcl_port disabled; telnet_port disabled; log_output {gdb_logfile}"
+        );
+        if let Some(serial) = &runner_cfg.probe_serial {
+            openocd_cmds.push_str(&format!("; adapter serial {serial}"));
+        }
+
+        let mut pipe = vec![openocd.executable, "-c".to_string(), openocd_cmds];
+        pipe.extend(openocd.args);
+
+        let commands = GdbCommands {
+            connection: vec![format!("target extended-remote | {}", pipe_args(&pipe))],
+            server_log: Some(PathBuf::from(gdb_logfile)),
+            load: runner_cfg
+                .load_commands(binary, "load")
+                .map_err(cfg_error)?,
+            rtt: rtt_commands(binary, &rtt_server)?,
+            pre_exit: runner_cfg.pre_exit_commands(binary).map_err(cfg_error)?,
+        };
+
+        Ok(Self {
+            session: GdbSession::spawn(runner_cfg, binary, workspace_dir, commands)?,
+            rtt_server,
+        })
+    }
+}
+
+/// Joins the command line for the shell that runs the pipe command of GDB.
+///
+/// Arguments with whitespace are quoted (e.g. `-c "adapter speed 4000"`).
+fn pipe_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            if arg.contains(char::is_whitespace) || arg.is_empty() {
+                format!("\"{}\"", arg.replace('"', "\\\""))
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Maximum duration to wait for the greeting of the RTT server.
+const RTT_GREETING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// RTT server of OpenOCD the defmt frames are read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RttServer {
+    pub port: u16,
+    /// Message the server sends on every connection to identify itself.
+    ///
+    /// Only set for `rtt-port = "auto"`, because older OpenOCD versions do not support it.
+    pub greeting: Option<String>,
+}
+
+impl RttServer {
+    /// Resolves the RTT server for the configured port.
+    ///
+    /// For [`RttPort::Auto`], a free port and a greeting unique to this run are picked.
+    pub fn new(port: RttPort) -> Result<Self, RunnerError> {
+        match port {
+            RttPort::Fixed(port) => Ok(Self {
+                port,
+                greeting: None,
+            }),
+            RttPort::Auto => {
+                let port = free_local_port()?;
+                let nanos = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .subsec_nanos();
+
+                Ok(Self {
+                    port,
+                    greeting: Some(format!(
+                        "embedded-runner-{:x}-{nanos:x}",
+                        std::process::id()
+                    )),
+                })
+            }
+        }
+    }
+
+    /// OpenOCD command to start the RTT server.
+    pub fn start_command(&self) -> String {
+        match &self.greeting {
+            Some(greeting) => format!("rtt server start {} 0 {greeting}", self.port),
+            None => format!("rtt server start {} 0", self.port),
+        }
+    }
+
+    /// Connects to the RTT server, and checks that it is the one started by this run.
+    pub async fn connect(&self) -> Result<TcpStream, RunnerError> {
+        let mut stream = connect_log_socket(self.port).await?;
+
+        if let Some(greeting) = &self.greeting {
+            let mut received = vec![0; greeting.len()];
+            let read =
+                tokio::time::timeout(RTT_GREETING_TIMEOUT, stream.read_exact(&mut received)).await;
+
+            if !matches!(read, Ok(Ok(_))) || received != greeting.as_bytes() {
+                return Err(RunnerError::Rtt(format!(
+                    "Server on port {} is not the RTT server started by this run.",
+                    self.port
+                )));
+            }
+        }
+
+        Ok(stream)
+    }
+}
+
+/// `monitor rtt` commands of OpenOCD to start the given RTT server.
+pub(crate) fn rtt_commands(
+    binary: &Path,
+    rtt_server: &RttServer,
+) -> Result<Vec<String>, RunnerError> {
+    let mut commands = rtt_setup_commands(binary)?;
+    commands.push(format!("monitor {}", rtt_server.start_command()));
+    Ok(commands)
+}
+
+/// `monitor rtt` commands of OpenOCD to find the RTT control block of the binary.
+pub(crate) fn rtt_setup_commands(binary: &Path) -> Result<Vec<String>, RunnerError> {
+    let (rtt_address, rtt_length) = find_rtt_block(binary).map_err(cfg_error)?;
+
+    Ok(vec![
+        format!("monitor rtt setup 0x{rtt_address:x} {rtt_length} \"SEGGER RTT\""),
+        "monitor rtt start".to_string(),
+    ])
+}
+
+impl Backend for OpenOcdBackend {
+    type LogStream = TcpStream;
+
+    async fn start_server(&mut self) -> Result<(), RunnerError> {
+        // OpenOCD is started by GDB on connection
+        Ok(())
+    }
+
+    async fn flash(&mut self) -> Result<(), RunnerError> {
+        self.session.flash().await
+    }
+
+    async fn setup_log_transport(&mut self) -> Result<TcpStream, RunnerError> {
+        self.session.start_rtt().await?;
+        self.rtt_server.connect().await
+    }
+
+    async fn run(&mut self, stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+        self.session.run(stop).await
+    }
+
+    async fn teardown(self) -> Result<(), RunnerError> {
+        self.session.teardown().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    use crate::{cfg::RttPort, RunnerError};
+
+    use super::RttServer;
+
+    #[tokio::test]
+    async fn auto_port_rejects_foreign_server() {
+        let rtt_server = RttServer::new(RttPort::Auto).unwrap();
+        let greeting = rtt_server.greeting.clone().unwrap();
+        assert!(
+            rtt_server.start_command().ends_with(&greeting),
+            "Greeting not passed to OpenOCD."
+        );
+
+        let listener = TcpListener::bind(("127.0.0.1", rtt_server.port))
+            .await
+            .unwrap();
+        let server = tokio::spawn(async move {
+            for message in ["x".repeat(greeting.len()), greeting] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                stream.write_all(message.as_bytes()).await.unwrap();
+                stream.write_all(b"defmt").await.unwrap();
+            }
+        });
+
+        assert!(
+            matches!(rtt_server.connect().await, Err(RunnerError::Rtt(_))),
+            "Foreign server accepted."
+        );
+        assert!(
+            rtt_server.connect().await.is_ok(),
+            "Started server rejected."
+        );
+
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,680 @@
+//! OpenOCD started once as background server, and reused by later runs.
+//!
+//! Every probe gets its own server.
+//! The PID and ports of a server are stored in `.embedded/openocd-server/<probe>.json`.
+//! A server is only killed if its PID still belongs to the started OpenOCD process,
+//! which is verified by the process start time on Linux.
+
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use path_slash::PathExt;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cfg::{OpenOcdServerConfig, ResolvedConfig, RttPort},
+    lock::{probe_filename, ProbeLock},
+    RunnerError,
+};
+
+use super::{
+    cfg_error, openocd::RttServer, target_error, Backend, GdbCommands, GdbSession, TargetStop,
+};
+
+/// Directory of the server states and logs in the `.embedded` directory.
+const SERVER_DIRNAME: &str = "openocd-server";
+/// Delay between checks if the server is ready, or shut down.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Maximum duration the server may take to shut down before it is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+/// Maximum duration the Tcl server may take to answer a command.
+const TCL_REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+/// Maximum length of a Tcl reply, because another process might listen on the Tcl port.
+const MAX_TCL_REPLY_LEN: u64 = 4096;
+/// Terminates commands sent to, and replies received from the Tcl server of OpenOCD.
+const TCL_TERMINATOR: u8 = 0x1a;
+
+/// Running OpenOCD background server.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ServerState {
+    pub pid: u32,
+    /// OpenOCD executable the server was started with.
+    #[serde(default)]
+    pub executable: String,
+    /// Search paths, configuration files and commands the server was started with.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Serial number of the debug probe the server is connected to.
+    #[serde(default)]
+    pub probe_serial: Option<String>,
+    pub gdb_port: u16,
+    pub tcl_port: u16,
+    pub rtt_port: u16,
+    /// Message the RTT server sends on every connection, if started for `rtt-port = "auto"`.
+    #[serde(default)]
+    pub rtt_greeting: Option<String>,
+    /// Key of the probe lock that must be held to stop the server.
+    #[serde(default)]
+    pub probe: Option<String>,
+    /// Start time of the process in clock ticks since boot, if known on this platform.
+    ///
+    /// Used to verify that the PID was not reused by another process.
+    #[serde(default)]
+    pub start_time: Option<u64>,
+}
+
+impl ServerState {
+    /// Returns `true` if the server runs with the given configuration.
+    fn matches(&self, other: &ServerState, rtt_port: RttPort) -> bool {
+        let rtt_matches = match rtt_port {
+            RttPort::Fixed(port) => self.rtt_port == port && self.rtt_greeting.is_none(),
+            // any port picked by a previous run is fine
+            RttPort::Auto => self.rtt_greeting.is_some(),
+        };
+
+        self.executable == other.executable
+            && self.args == other.args
+            && self.probe_serial == other.probe_serial
+            && self.gdb_port == other.gdb_port
+            && self.tcl_port == other.tcl_port
+            && rtt_matches
+    }
+
+    /// Returns `true` if both servers would listen on the same port.
+    fn shares_port(&self, other: &ServerState) -> bool {
+        let ports = [self.gdb_port, self.tcl_port, self.rtt_port];
+        [other.gdb_port, other.tcl_port, other.rtt_port]
+            .iter()
+            .any(|port| ports.contains(port))
+    }
+
+    fn rtt_server(&self) -> RttServer {
+        RttServer {
+            port: self.rtt_port,
+            greeting: self.rtt_greeting.clone(),
+        }
+    }
+}
+
+/// OpenOCD background server that is started by the first run, and reused by later runs.
+pub struct OpenOcdServerBackend {
+    session: GdbSession,
+    embedded_dir: PathBuf,
+    /// Server files of the probe used for the run, without extension.
+    server_files: PathBuf,
+    /// Server state with an unknown PID and RTT port, because the server might not be started yet.
+    state: ServerState,
+    rtt_port: RttPort,
+    probe_lock_timeout: Duration,
+}
+
+impl OpenOcdServerBackend {
+    pub fn new(
+        main_cfg: &ResolvedConfig,
+        server_cfg: &OpenOcdServerConfig,
+        binary: &Path,
+        output_dir: &Path,
+    ) -> Result<Self, RunnerError> {
+        let runner_cfg = &main_cfg.runner_cfg;
+        let openocd = runner_cfg.openocd_command(binary).map_err(cfg_error)?;
+        let probe = runner_cfg.probe_key();
+        let server_files = main_cfg
+            .embedded_dir
+            .join(SERVER_DIRNAME)
+            .join(probe_filename(probe.as_deref().unwrap_or_default()));
+        let state = ServerState {
+            pid: 0,
+            executable: openocd.executable,
+            args: openocd.args,
+            probe_serial: runner_cfg.probe_serial.clone(),
+            gdb_port: server_cfg.gdb_port(),
+            tcl_port: server_cfg.tcl_port(),
+            rtt_port: 0,
+            rtt_greeting: None,
+            probe,
+            start_time: None,
+        };
+
+        // RTT server is started once with OpenOCD, so only the control block is set up per run
+        let rtt = super::openocd::rtt_setup_commands(binary)?;
+        // stopped for the next run that may use a binary with another control block
+        let mut pre_exit = runner_cfg.pre_exit_commands(binary).map_err(cfg_error)?;
+        pre_exit.push("monitor rtt stop".to_string());
+
+        let commands = GdbCommands {
+            connection: vec![
+                format!("target extended-remote localhost:{}", state.gdb_port),
+                format!("set logging file {}", runner_cfg.gdb_logfile(output_dir)),
+            ],
+            server_log: Some(server_files.with_extension("log")),
+            load: runner_cfg
+                .load_commands(binary, "load")
+                .map_err(cfg_error)?,
+            rtt,
+            pre_exit,
+        };
+
+        Ok(Self {
+            session: GdbSession::spawn(runner_cfg, binary, &main_cfg.workspace_dir, commands)?,
+            embedded_dir: main_cfg.embedded_dir.clone(),
+            server_files,
+            state,
+            rtt_port: runner_cfg.rtt_server_port(),
+            probe_lock_timeout: runner_cfg.probe_lock_timeout(),
+        })
+    }
+}
+
+impl Backend for OpenOcdServerBackend {
+    type LogStream = TcpStream;
+
+    async fn start_server(&mut self) -> Result<(), RunnerError> {
+        let state_file = self.server_files.with_extension("json");
+
+        if let Some(running) = read_state(&state_file).await {
+            if !running.matches(&self.state, self.rtt_port) {
+                log::info!("Restarting OpenOCD server, because its configuration changed.");
+            } else if is_alive(&running).await {
+                log::debug!("Reusing OpenOCD server with PID {}.", running.pid);
+                self.state = running;
+                return Ok(());
+            } else {
+                log::info!("Restarting OpenOCD server, because it does not respond.");
+            }
+
+            stop(&state_file).await?;
+        }
+
+        let rtt_server = RttServer::new(self.rtt_port)?;
+        self.state.rtt_port = rtt_server.port;
+        self.state.rtt_greeting = rtt_server.greeting;
+
+        // servers of other probes block the ports if they were started with the same ports
+        let server_dir = self.server_files.parent().unwrap_or(Path::new("."));
+        for other_file in state_files(server_dir).await {
+            if other_file == state_file {
+                continue;
+            }
+
+            if let Some(other) = read_state(&other_file).await {
+                if other.shares_port(&self.state) {
+                    // a run in another process might still use the server
+                    let Some(other_probe) = &other.probe else {
+                        return Err(RunnerError::Setup(format!(
+                            "OpenOCD server with PID {} of an unknown probe uses the same ports. \
+                            Stop it with `embedded-runner stop-server`, \
+                            or set other ports per probe in `[openocd-server]`.",
+                            other.pid
+                        )));
+                    };
+                    let _other_lock = ProbeLock::acquire(
+                        &self.embedded_dir,
+                        other_probe,
+                        self.probe_lock_timeout,
+                    )
+                    .await?;
+
+                    log::info!(
+                        "Stopping OpenOCD server with PID {} of another probe, because it uses the same ports.",
+                        other.pid
+                    );
+                    stop(&other_file).await?;
+                }
+            }
+        }
+
+        start(&self.server_files, &mut self.state).await
+    }
+
+    async fn flash(&mut self) -> Result<(), RunnerError> {
+        self.session.flash().await
+    }
+
+    async fn setup_log_transport(&mut self) -> Result<TcpStream, RunnerError> {
+        self.session.start_rtt().await?;
+        self.state.rtt_server().connect().await
+    }
+
+    async fn run(&mut self, stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+        self.session.run(stop).await
+    }
+
+    async fn teardown(self) -> Result<(), RunnerError> {
+        // server keeps running for later runs
+        self.session.teardown().await
+    }
+}
+
+/// Starts OpenOCD as background server, and waits until it accepts connections.
+///
+/// The state and log of the server are stored in the given server files with `.json` and `.log` extensions.
+async fn start(server_files: &Path, state: &mut ServerState) -> Result<(), RunnerError> {
+    let setup_err = |err: std::io::Error| {
+        RunnerError::Setup(format!(
+            "Could not store OpenOCD server state. Cause: {err}"
+        ))
+    };
+    if let Some(server_dir) = server_files.parent() {
+        tokio::fs::create_dir_all(server_dir)
+            .await
+            .map_err(setup_err)?;
+    }
+
+    let log = server_files.with_extension("log");
+    let log_slash = log
+        .to_slash()
+        .expect("OpenOCD log file must be a valid filepath.");
+
+    let mut cmd = std::process::Command::new(&state.executable);
+    if let Some(serial) = &state.probe_serial {
+        cmd.args(["-c", &format!("adapter serial {serial}")]);
+    }
+    cmd.args([
+        "-c",
+        &format!("gdb_port {}", state.gdb_port),
+        "-c",
+        &format!("tcl_port {}", state.tcl_port),
+        "-c",
+        "telnet_port disabled",
+        "-c",
+        &format!("log_output {log_slash}"),
+    ])
+    .args(&state.args)
+    .args(["-c", "init", "-c", &state.rtt_server().start_command()])
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null());
+
+    // server must outlive the runner, and must not receive Ctrl-C from the terminal
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+
+    let mut child = cmd.spawn().map_err(|err| {
+        RunnerError::Connection(format!("Could not start OpenOCD server. Cause: {err}"))
+    })?;
+    state.pid = child.id();
+    state.start_time = process_start_time(state.pid);
+
+    // reaped in the background, so an exited server does not stay a zombie while the runner is running
+    let (exit_sender, mut exited) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = exit_sender.send(child.wait());
+    });
+
+    log::info!(
+        "Started OpenOCD server with PID {} (GDB port {}, Tcl port {}).",
+        state.pid,
+        state.gdb_port,
+        state.tcl_port
+    );
+
+    // stored right away, so the server may be stopped even if it never gets ready
+    let content = serde_json::to_string_pretty(state).expect("Server state is valid JSON.");
+    tokio::fs::write(server_files.with_extension("json"), content)
+        .await
+        .map_err(setup_err)?;
+
+    // setup timeout of the run limits waiting for the server
+    while !is_alive(state).await {
+        if let Ok(status) = exited.try_recv() {
+            let status = match status {
+                Ok(status) => status.to_string(),
+                Err(err) => err.to_string(),
+            };
+            return Err(target_error(
+                format!(
+                    "OpenOCD server exited with '{status}'. See '{}'.",
+                    log.display()
+                ),
+                Some(&log),
+            ));
+        }
+
+        tokio::time::sleep(READY_POLL_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+/// Shuts down the OpenOCD background servers of all probes.
+pub async fn stop_server(embedded_dir: &Path) -> Result<(), RunnerError> {
+    let state_files = state_files(&embedded_dir.join(SERVER_DIRNAME)).await;
+
+    if state_files.is_empty() {
+        log::info!("No OpenOCD server is running.");
+    }
+
+    for state_file in state_files {
+        stop(&state_file).await?;
+    }
+
+    Ok(())
+}
+
+/// Shuts down the OpenOCD background server of the given state file.
+///
+/// The server is killed if it does not shut down within [`SHUTDOWN_TIMEOUT`],
+/// or if it does not respond on its Tcl port, but its process is still running.
+async fn stop(state_file: &Path) -> Result<(), RunnerError> {
+    if let Some(state) = read_state(state_file).await {
+        if is_alive(&state).await {
+            let shutdown = match tcl_connect(state.tcl_port).await {
+                Ok(mut stream) => {
+                    stream
+                        .write_all(&[b"shutdown".as_slice(), &[TCL_TERMINATOR]].concat())
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            if let Err(err) = shutdown {
+                log::debug!("Could not shut down OpenOCD server over Tcl. Cause: {err}");
+            }
+
+            let closed = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+                while is_alive(&state).await {
+                    tokio::time::sleep(READY_POLL_INTERVAL).await;
+                }
+            })
+            .await;
+
+            if closed.is_err() {
+                log::debug!("OpenOCD server did not shut down in time.");
+                kill_server(&state).await;
+            }
+
+            log::info!("Stopped OpenOCD server with PID {}.", state.pid);
+        } else if is_own_process(&state) == Some(true) {
+            // server never got ready, or hangs
+            kill_server(&state).await;
+            log::info!("Stopped OpenOCD server with PID {}.", state.pid);
+        } else {
+            // PID might already belong to another process
+            log::debug!("OpenOCD server with PID {} is not running.", state.pid);
+        }
+    }
+
+    tokio::fs::remove_file(state_file).await.map_err(|err| {
+        RunnerError::Setup(format!(
+            "Could not remove OpenOCD server state. Cause: {err}"
+        ))
+    })
+}
+
+/// Returns the state files of all servers in the given server directory.
+async fn state_files(server_dir: &Path) -> Vec<PathBuf> {
+    let mut state_files = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(server_dir).await else {
+        return state_files;
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            state_files.push(path);
+        }
+    }
+
+    state_files
+}
+
+async fn read_state(state_file: &Path) -> Option<ServerState> {
+    let content = tokio::fs::read_to_string(state_file).await.ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Returns `true` if the started OpenOCD server answers on its Tcl port.
+///
+/// The replies are checked, because another process might listen on the Tcl port.
+async fn is_alive(state: &ServerState) -> bool {
+    if is_own_process(state) == Some(false) {
+        return false;
+    }
+
+    let replies = tokio::time::timeout(TCL_REPLY_TIMEOUT, async {
+        let mut tcl = BufStream::new(tcl_connect(state.tcl_port).await?);
+        let version = tcl_query(&mut tcl, "version").await?;
+        let pid = tcl_query(&mut tcl, "pid").await?;
+        Ok::<_, std::io::Error>((version, pid))
+    })
+    .await;
+
+    match replies {
+        // `pid` is only available if the Jim Tcl interpreter of OpenOCD was built with `exec` support
+        Ok(Ok((version, pid))) => {
+            version.starts_with("Open On-Chip Debugger")
+                && !matches!(pid.trim().parse::<u32>(), Ok(pid) if pid != state.pid)
+        }
+        _ => false,
+    }
+}
+
+async fn tcl_connect(port: u16) -> std::io::Result<TcpStream> {
+    TcpStream::connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port)).await
+}
+
+/// Sends the command to the Tcl server, and returns its reply.
+async fn tcl_query(tcl: &mut BufStream<TcpStream>, command: &str) -> std::io::Result<String> {
+    tcl.write_all(&[command.as_bytes(), &[TCL_TERMINATOR]].concat())
+        .await?;
+    tcl.flush().await?;
+
+    let mut reply = Vec::new();
+    (&mut *tcl)
+        .take(MAX_TCL_REPLY_LEN)
+        .read_until(TCL_TERMINATOR, &mut reply)
+        .await?;
+    if reply.pop() != Some(TCL_TERMINATOR) {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+/// Returns `Some(true)` if the PID of the server still belongs to the started OpenOCD process,
+/// or `None` if this cannot be verified on this platform.
+fn is_own_process(state: &ServerState) -> Option<bool> {
+    let start_time = state.start_time?;
+    Some(process_start_time(state.pid) == Some(start_time))
+}
+
+/// Returns the start time of the process with the given PID in clock ticks since boot.
+#[cfg(target_os = "linux")]
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // fields after the executable name in parentheses start with the state (field 3), start time is field 22
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_start_time(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// Kills the server, if its PID still belongs to the started OpenOCD process.
+async fn kill_server(state: &ServerState) {
+    let pid = state.pid;
+    match is_own_process(state) {
+        Some(true) => {}
+        // process exited, and the PID might already belong to another process
+        Some(false) => return,
+        None => {
+            log::warn!(
+                "OpenOCD server with PID {pid} is not killed, because its PID cannot be verified on this platform."
+            );
+            return;
+        }
+    }
+
+    // PIDs are only verified on Linux
+    if let Err(err) = tokio::process::Command::new("kill")
+        .arg(pid.to_string())
+        .status()
+        .await
+    {
+        log::warn!("Could not kill OpenOCD server with PID {pid}. Cause: {err}");
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use std::path::Path;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
+
+    use super::{
+        is_alive, process_start_time, stop_server, ServerState, SERVER_DIRNAME, TCL_TERMINATOR,
+    };
+
+    /// Stores the state of a fake server with the given Tcl port, and returns the server.
+    ///
+    /// The stored start time differs from the one of the server, if its PID was reused.
+    fn fake_server(
+        embedded_dir: &Path,
+        tcl_port: u16,
+        reused_pid: bool,
+    ) -> (tokio::process::Child, ServerState) {
+        let server_dir = embedded_dir.join(SERVER_DIRNAME);
+        std::fs::create_dir_all(&server_dir).unwrap();
+
+        let server = tokio::process::Command::new("sleep")
+            .arg("100")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let pid = server.id().unwrap();
+        let start_time = process_start_time(pid).unwrap();
+        let state = ServerState {
+            pid,
+            executable: "openocd".to_string(),
+            args: vec!["-f".to_string(), ".embedded/openocd.cfg".to_string()],
+            probe_serial: None,
+            gdb_port: 1,
+            tcl_port,
+            rtt_port: 1,
+            rtt_greeting: None,
+            probe: Some(".embedded/openocd.cfg".to_string()),
+            start_time: Some(if reused_pid {
+                start_time + 1
+            } else {
+                start_time
+            }),
+        };
+        std::fs::write(
+            server_dir.join("_embedded_openocd_cfg.json"),
+            serde_json::to_string(&state).unwrap(),
+        )
+        .unwrap();
+
+        (server, state)
+    }
+
+    /// Starts a fake Tcl server that answers every command with the given reply, and returns its port.
+    async fn fake_tcl(reply: &'static str) -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut stream = BufStream::new(stream);
+                    let mut command = Vec::new();
+                    while stream
+                        .read_until(TCL_TERMINATOR, &mut command)
+                        .await
+                        .is_ok_and(|len| len > 0)
+                    {
+                        let _ = stream
+                            .write_all(&[reply.as_bytes(), &[TCL_TERMINATOR]].concat())
+                            .await;
+                        let _ = stream.flush().await;
+                        command.clear();
+                    }
+                });
+            }
+        });
+
+        port
+    }
+
+    fn assert_state_removed(embedded_dir: &Path) {
+        assert!(
+            !embedded_dir
+                .join(SERVER_DIRNAME)
+                .join("_embedded_openocd_cfg.json")
+                .exists(),
+            "Server state not removed."
+        );
+    }
+
+    async fn assert_stopped(embedded_dir: &Path, mut server: tokio::process::Child) {
+        stop_server(embedded_dir).await.unwrap();
+
+        let status = tokio::time::timeout(std::time::Duration::from_secs(5), server.wait())
+            .await
+            .expect("Server not stopped.")
+            .unwrap();
+        assert!(!status.success(), "Server not killed.");
+        assert_state_removed(embedded_dir);
+    }
+
+    #[tokio::test]
+    async fn stop_kills_unresponsive_server() {
+        let embedded_dir = std::env::temp_dir().join("emb-runner-stop-server");
+        // port 1 is privileged => no Tcl server listens there
+        let (server, _) = fake_server(&embedded_dir, 1, false);
+
+        assert_stopped(&embedded_dir, server).await;
+    }
+
+    #[tokio::test]
+    async fn stop_kills_server_ignoring_shutdown() {
+        let embedded_dir = std::env::temp_dir().join("emb-runner-stop-server-shutdown");
+        // Tcl server keeps answering after the shutdown command
+        let tcl_port = fake_tcl("Open On-Chip Debugger 0.12.0").await;
+        let (server, _) = fake_server(&embedded_dir, tcl_port, false);
+
+        assert_stopped(&embedded_dir, server).await;
+    }
+
+    #[tokio::test]
+    async fn stop_keeps_process_with_reused_pid() {
+        let embedded_dir = std::env::temp_dir().join("emb-runner-stop-server-reused");
+        let (mut server, _) = fake_server(&embedded_dir, 1, true);
+
+        stop_server(&embedded_dir).await.unwrap();
+
+        assert!(
+            server.try_wait().unwrap().is_none(),
+            "Process with reused PID killed."
+        );
+        assert_state_removed(&embedded_dir);
+    }
+
+    #[tokio::test]
+    async fn foreign_tcl_listener_is_not_alive() {
+        let embedded_dir = std::env::temp_dir().join("emb-runner-foreign-tcl");
+        let tcl_port = fake_tcl("HTTP/1.1 400 Bad Request").await;
+        let (_server, state) = fake_server(&embedded_dir, tcl_port, false);
+
+        assert!(!is_alive(&state).await, "Foreign listener accepted.");
+
+        let openocd_port = fake_tcl("Open On-Chip Debugger 0.12.0").await;
+        let openocd = ServerState {
+            tcl_port: openocd_port,
+            ..state
+        };
+        assert!(is_alive(&openocd).await, "OpenOCD server not accepted.");
+    }
+}
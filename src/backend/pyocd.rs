@@ -0,0 +1,204 @@
+//! GDB server of pyOCD, which supports targets through CMSIS-Packs.
+//!
+//! RTT is read by pyOCD itself, and forwarded to its telnet port.
+
+use std::path::{Path, PathBuf};
+
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cfg::{find_rtt_block, PyOcdConfig, RttPort, RunnerConfig},
+    RunnerError,
+};
+
+use super::{
+    cfg_error, connect_log_socket, free_local_port, Backend, GdbCommands, GdbSession,
+    ServerProcess, TargetStop,
+};
+
+/// Default pyOCD executable.
+pub const DEFAULT_PYOCD: &str = "pyocd";
+
+/// Messages pyOCD logs once its GDB server accepts connections.
+const READY_MESSAGES: [&str; 2] = [
+    "gdb server listening on port",
+    // older pyOCD versions
+    "gdb server started on port",
+];
+
+/// GDB server of pyOCD started for the run, and controlled by GDB.
+///
+/// defmt frames are read from the telnet port of pyOCD, which forwards RTT channel 0.
+pub struct PyOcdBackend {
+    cfg: PyOcdConfig,
+    args: Vec<String>,
+    workspace_dir: PathBuf,
+    logfile: PathBuf,
+    rtt_port: u16,
+    pyocd: Option<ServerProcess>,
+    session: GdbSession,
+}
+
+impl PyOcdBackend {
+    pub fn new(
+        runner_cfg: &RunnerConfig,
+        binary: &Path,
+        workspace_dir: &Path,
+        output_dir: &Path,
+    ) -> Result<Self, RunnerError> {
+        let cfg = runner_cfg.pyocd_cfg().map_err(cfg_error)?.clone();
+        let rtt_block = find_rtt_block(binary).map_err(cfg_error)?;
+        // pyOCD is started for this run only, so the picked port needs no greeting
+        let rtt_port = match runner_cfg.rtt_server_port() {
+            RttPort::Fixed(port) => port,
+            RttPort::Auto => free_local_port()?,
+        };
+        let logfile = output_dir.join("pyocd.log");
+
+        let commands = GdbCommands {
+            connection: vec![format!("target remote localhost:{}", cfg.gdb_port())],
+            server_log: Some(logfile.clone()),
+            load: runner_cfg
+                .load_commands(binary, "load")
+                .map_err(cfg_error)?,
+            // pyOCD starts RTT on its own
+            rtt: Vec::new(),
+            pre_exit: runner_cfg.pre_exit_commands(binary).map_err(cfg_error)?,
+        };
+
+        Ok(Self {
+            args: args(
+                &cfg,
+                runner_cfg.probe_serial.as_deref(),
+                rtt_port,
+                rtt_block,
+            ),
+            cfg,
+            workspace_dir: workspace_dir.to_path_buf(),
+            logfile,
+            rtt_port,
+            pyocd: None,
+            session: GdbSession::spawn(runner_cfg, binary, workspace_dir, commands)?,
+        })
+    }
+}
+
+impl Backend for PyOcdBackend {
+    type LogStream = TcpStream;
+
+    async fn start_server(&mut self) -> Result<(), RunnerError> {
+        let pyocd = ServerProcess::spawn(
+            "pyOCD",
+            self.cfg.executable.as_deref().unwrap_or(DEFAULT_PYOCD),
+            &self.args,
+            &self.workspace_dir,
+            &self.logfile,
+        )?;
+
+        // stored before waiting, so pyOCD is killed in `teardown()` if it never gets ready
+        self.pyocd
+            .insert(pyocd)
+            .wait_until_ready(&READY_MESSAGES)
+            .await
+    }
+
+    async fn flash(&mut self) -> Result<(), RunnerError> {
+        self.session.flash().await
+    }
+
+    async fn setup_log_transport(&mut self) -> Result<TcpStream, RunnerError> {
+        connect_log_socket(self.rtt_port).await
+    }
+
+    async fn run(&mut self, stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+        self.session.run(stop).await
+    }
+
+    async fn teardown(self) -> Result<(), RunnerError> {
+        let result = self.session.teardown().await;
+
+        if let Some(pyocd) = self.pyocd {
+            let _ = pyocd.kill().await;
+        }
+
+        result
+    }
+}
+
+/// Arguments to start the GDB server of pyOCD.
+///
+/// The RTT control block is passed, so pyOCD does not need to scan the RAM for it.
+pub fn args(
+    cfg: &PyOcdConfig,
+    probe_serial: Option<&str>,
+    rtt_port: u16,
+    (rtt_address, rtt_length): (u64, u64),
+) -> Vec<String> {
+    let mut args = vec![
+        "gdbserver".to_string(),
+        "--target".to_string(),
+        cfg.target.clone(),
+    ];
+
+    for pack in &cfg.packs {
+        args.push("--pack".to_string());
+        args.push(pack.display().to_string());
+    }
+
+    if let Some(serial) = probe_serial {
+        args.push("--uid".to_string());
+        args.push(serial.to_string());
+    }
+
+    args.extend([
+        "--port".to_string(),
+        cfg.gdb_port().to_string(),
+        "--telnet-port".to_string(),
+        rtt_port.to_string(),
+        "-O".to_string(),
+        "rtt.enable=true".to_string(),
+        "-O".to_string(),
+        format!("rtt.address=0x{rtt_address:x}"),
+        "-O".to_string(),
+        format!("rtt.size={rtt_length}"),
+    ]);
+    args.extend(cfg.args.iter().cloned());
+
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cfg::PyOcdConfig;
+
+    #[test]
+    fn gdbserver_with_pack_and_rtt() {
+        let cfg: PyOcdConfig = toml::from_str(
+            "
+            target = \"stm32h743zitx\"
+            packs = [\".embedded/Keil.STM32H7xx_DFP.pdsc\"]
+            gdb-port = 3334
+            ",
+        )
+        .unwrap();
+
+        let args = super::args(&cfg, Some("0670FF48"), 19021, (0x2000_0000, 48)).join(" ");
+
+        assert!(
+            args.starts_with(
+                "gdbserver --target stm32h743zitx --pack .embedded/Keil.STM32H7xx_DFP.pdsc"
+            ),
+            "Target or pack not set."
+        );
+        assert!(args.contains("--uid 0670FF48"), "Probe serial not passed.");
+        assert!(
+            args.contains("--port 3334 --telnet-port 19021"),
+            "Ports not set."
+        );
+        assert!(
+            args.ends_with("-O rtt.enable=true -O rtt.address=0x20000000 -O rtt.size=48"),
+            "RTT control block not passed."
+        );
+    }
+}
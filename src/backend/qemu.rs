@@ -0,0 +1,225 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+};
+
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cfg::{QemuConfig, QemuTransport, RunnerConfig},
+    process::Registration,
+    RunnerError,
+};
+
+use super::{cfg_error, connect_log_socket, Backend, GdbCommands, GdbSession, TargetStop};
+
+/// Default QEMU executable for ARM targets.
+pub const DEFAULT_QEMU: &str = "qemu-system-arm";
+
+/// Id of the QEMU character device the defmt frames are written to.
+const DEFMT_CHARDEV: &str = "defmt";
+
+/// QEMU instance emulating the target.
+#[derive(Debug)]
+pub struct Qemu {
+    child: tokio::process::Child,
+    _registration: Registration,
+}
+
+impl Qemu {
+    /// Starts QEMU with the CPU halted until GDB connects to the gdbstub.
+    ///
+    /// Output of QEMU is written to the given logfile.
+    pub fn spawn(
+        cfg: &QemuConfig,
+        binary: &Path,
+        workspace_dir: &Path,
+        logfile: &Path,
+    ) -> std::io::Result<Self> {
+        let executable = cfg.executable.as_deref().unwrap_or(DEFAULT_QEMU);
+        let log = std::fs::File::create(logfile)?;
+
+        log::debug!(
+            "Starting QEMU: {executable} {}",
+            args(cfg, binary).join(" ")
+        );
+
+        let mut cmd = tokio::process::Command::new(executable);
+        cmd.args(args(cfg, binary))
+            .current_dir(workspace_dir)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .kill_on_drop(true);
+
+        let (child, registration) = crate::process::spawn(&mut cmd)?;
+
+        Ok(Qemu {
+            child,
+            _registration: registration,
+        })
+    }
+
+    /// Stops QEMU.
+    pub async fn kill(mut self) -> std::io::Result<ExitStatus> {
+        self.child.kill().await?;
+        self.child.wait().await
+    }
+}
+
+/// QEMU emulating the target, controlled by GDB over the gdbstub of QEMU.
+///
+/// defmt frames are read from a QEMU socket, so no RTT setup is needed.
+pub struct QemuBackend {
+    cfg: QemuConfig,
+    binary: PathBuf,
+    workspace_dir: PathBuf,
+    logfile: PathBuf,
+    qemu: Option<Qemu>,
+    session: GdbSession,
+}
+
+impl QemuBackend {
+    pub fn new(
+        runner_cfg: &RunnerConfig,
+        binary: &Path,
+        workspace_dir: &Path,
+        output_dir: &Path,
+    ) -> Result<Self, RunnerError> {
+        let cfg = runner_cfg.qemu_cfg().map_err(cfg_error)?.clone();
+        let commands = GdbCommands {
+            connection: vec![format!("target remote localhost:{}", cfg.gdb_port())],
+            server_log: None,
+            // QEMU loads the binary on start
+            load: runner_cfg.load_commands(binary, "").map_err(cfg_error)?,
+            rtt: Vec::new(),
+            pre_exit: runner_cfg.pre_exit_commands(binary).map_err(cfg_error)?,
+        };
+
+        Ok(Self {
+            cfg,
+            binary: binary.to_path_buf(),
+            workspace_dir: workspace_dir.to_path_buf(),
+            logfile: output_dir.join("qemu.log"),
+            qemu: None,
+            session: GdbSession::spawn(runner_cfg, binary, workspace_dir, commands)?,
+        })
+    }
+}
+
+impl Backend for QemuBackend {
+    type LogStream = TcpStream;
+
+    async fn start_server(&mut self) -> Result<(), RunnerError> {
+        let qemu = Qemu::spawn(&self.cfg, &self.binary, &self.workspace_dir, &self.logfile)
+            .map_err(|err| RunnerError::Qemu(err.to_string()))?;
+        self.qemu = Some(qemu);
+        Ok(())
+    }
+
+    async fn flash(&mut self) -> Result<(), RunnerError> {
+        self.session.flash().await
+    }
+
+    async fn setup_log_transport(&mut self) -> Result<TcpStream, RunnerError> {
+        connect_log_socket(self.cfg.log_port()).await
+    }
+
+    async fn run(&mut self, stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+        self.session.run(stop).await
+    }
+
+    async fn teardown(self) -> Result<(), RunnerError> {
+        let result = self.session.teardown().await;
+
+        if let Some(qemu) = self.qemu {
+            let _ = qemu.kill().await;
+        }
+
+        result
+    }
+}
+
+/// Arguments to start QEMU for the given binary.
+pub fn args(cfg: &QemuConfig, binary: &Path) -> Vec<String> {
+    let mut args = vec!["-machine".to_string(), cfg.machine.clone()];
+
+    if let Some(cpu) = &cfg.cpu {
+        args.push("-cpu".to_string());
+        args.push(cpu.clone());
+    }
+
+    args.extend([
+        "-display".to_string(),
+        "none".to_string(),
+        "-monitor".to_string(),
+        "none".to_string(),
+        "-chardev".to_string(),
+        format!(
+            "socket,id={DEFMT_CHARDEV},host=127.0.0.1,port={},server=on,wait=off",
+            cfg.log_port()
+        ),
+    ]);
+
+    match cfg.transport {
+        QemuTransport::Semihosting => {
+            // `target=native` prevents semihosting output from being sent to GDB
+            args.push("-semihosting-config".to_string());
+            args.push(format!("enable=on,target=native,chardev={DEFMT_CHARDEV}"));
+        }
+        QemuTransport::Serial => {
+            args.push("-serial".to_string());
+            args.push(format!("chardev:{DEFMT_CHARDEV}"));
+        }
+    }
+
+    args.extend([
+        "-gdb".to_string(),
+        format!("tcp:127.0.0.1:{}", cfg.gdb_port()),
+        "-S".to_string(),
+        "-kernel".to_string(),
+        binary.display().to_string(),
+    ]);
+    args.extend(cfg.args.iter().cloned());
+
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::cfg::QemuConfig;
+
+    #[test]
+    fn semihosting_to_socket() {
+        let cfg: QemuConfig = toml::from_str(
+            "
+            machine = \"lm3s6965evb\"
+            cpu = \"cortex-m3\"
+            gdb-port = 3333
+            ",
+        )
+        .unwrap();
+
+        let args = super::args(&cfg, &PathBuf::from("target/emb-test")).join(" ");
+
+        assert!(
+            args.starts_with("-machine lm3s6965evb -cpu cortex-m3"),
+            "Machine or CPU not set."
+        );
+        assert!(
+            args.contains("-chardev socket,id=defmt,host=127.0.0.1,port=19021,server=on,wait=off"),
+            "Socket for defmt frames missing."
+        );
+        assert!(
+            args.contains("-semihosting-config enable=on,target=native,chardev=defmt"),
+            "Semihosting not redirected to the socket."
+        );
+        assert!(
+            args.contains("-gdb tcp:127.0.0.1:3333 -S -kernel target/emb-test"),
+            "Gdbstub or binary missing."
+        );
+    }
+}
@@ -1,9 +1,15 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use object::{Object, ObjectSymbol};
 use path_slash::{PathBufExt, PathExt};
 use tera::{Context, Tera};
 
+use crate::defmt::EndConditions;
+
 #[derive(Debug, Clone, clap::Parser)]
 pub struct CliConfig {
     #[arg(long, short = 'v')]
@@ -28,6 +34,10 @@ pub enum ConfigError {
     Fs(#[from] std::io::Error),
     #[error("{}", .0)]
     DeToml(#[from] toml::de::Error),
+    #[error("Invalid completion pattern. Cause: {}", .0)]
+    CompletionPattern(regex::Error),
+    #[error("No board '{}' in the `[boards]` section of the runner configuration.", .0)]
+    UnknownBoard(String),
 }
 
 pub fn get_cfg(runner_cfg: &Option<PathBuf>, verbose: bool) -> Result<ResolvedConfig, ConfigError> {
@@ -60,10 +70,37 @@ pub fn get_cfg(runner_cfg: &Option<PathBuf>, verbose: bool) -> Result<ResolvedCo
     })
 }
 
+impl ResolvedConfig {
+    /// Applies the board profile and the probe serial set for the run.
+    pub fn for_run(&self, run_cfg: &RunCmdConfig) -> Result<ResolvedConfig, ConfigError> {
+        let mut resolved = self.clone();
+
+        if let Some(board) = &run_cfg.board {
+            let board_cfg = self
+                .runner_cfg
+                .boards
+                .get(board)
+                .ok_or_else(|| ConfigError::UnknownBoard(board.clone()))?;
+            resolved.runner_cfg.apply_board(board_cfg);
+        }
+        if let Some(probe_serial) = &run_cfg.probe_serial {
+            resolved.runner_cfg.probe_serial = Some(probe_serial.clone());
+        }
+
+        Ok(resolved)
+    }
+}
+
 #[derive(Debug, Clone, clap::Parser)]
 pub enum Cmd {
     Run(RunCmdConfig),
     Collect(CollectCmdConfig),
+    /// Decodes recorded RTT data without running the binary.
+    Decode(DecodeCmdConfig),
+    /// Shuts down the OpenOCD background servers started by previous runs.
+    StopServer,
+    /// Runs binaries received over TCP from `run --remote` on the probes of this host.
+    Agent(AgentCmdConfig),
 }
 
 #[derive(Debug, Clone, clap::Parser)]
@@ -93,13 +130,113 @@ pub struct RunCmdConfig {
     /// Default: `.embedded/test_run_data.json`
     #[arg(long)]
     pub data_filepath: Option<PathBuf>,
+    /// `true`: Writes a JUnit XML report `junit.xml` next to the coverage file.
+    ///
+    /// This setting overwrites the one optionally set in the runner configuration.
+    #[arg(long)]
+    pub junit: Option<bool>,
+    /// Maximum duration in seconds to flash the binary and setup the RTT connection.
+    ///
+    /// This setting overwrites the ones optionally set in the runner configuration.
+    #[arg(long)]
+    pub setup_timeout: Option<u64>,
+    /// Maximum duration in seconds of the test run after the RTT connection is established.
+    ///
+    /// This setting overwrites the ones optionally set in the runner configuration.
+    #[arg(long)]
+    pub execution_timeout: Option<u64>,
+    /// Maximum duration in seconds of one defmt test.
+    ///
+    /// This setting overwrites the ones optionally set in the runner configuration.
+    #[arg(long)]
+    pub test_timeout: Option<u64>,
+    /// Regex pattern for defmt messages that signal the end of the run.
+    ///
+    /// This setting overwrites the one optionally set in the runner configuration.
+    #[arg(long)]
+    pub completion_pattern: Option<String>,
+    /// Name of the board in the `[boards]` section of the runner configuration to run the binary on.
+    #[arg(long, env = "EMBEDDED_RUNNER_BOARD")]
+    pub board: Option<String>,
+    /// Names of boards in the `[boards]` section of the runner configuration to run the binary on one after another.
+    ///
+    /// Results of each board are stored in a subdirectory of the output directory that is named after the board.
+    /// This setting takes precedence over `--board`.
+    #[arg(long, value_delimiter = ',')]
+    pub boards: Vec<String>,
+    /// `true`: Runs the binary on all boards set with `--boards` at the same time.
+    ///
+    /// Boards that share a probe, or a fixed port on the host (e.g. the RTT port) still run one after another.
+    #[arg(long, requires = "boards")]
+    pub parallel: bool,
+    /// Address of an agent started with `embedded-runner agent` that runs the binary on its host (e.g. `lab-pc:7500`).
+    ///
+    /// The runner configuration of the agent is used to run the binary.
+    #[arg(long, conflicts_with = "boards")]
+    pub remote: Option<String>,
+    /// Serial number of the debug probe to use.
+    ///
+    /// This setting overwrites the ones optionally set in the runner configuration.
+    #[arg(long, env = "EMBEDDED_RUNNER_PROBE_SERIAL")]
+    pub probe_serial: Option<String>,
     /// Filepath to the binary that should be run on the embedded device.
     pub binary: PathBuf,
 }
 
+#[derive(Debug, Clone, clap::Parser)]
+pub struct AgentCmdConfig {
+    /// Filepath to a TOML file that contains the runner configuration.
+    ///
+    /// Default: `.embedded/runner.toml`
+    #[arg(long)]
+    pub runner_cfg: Option<PathBuf>,
+    /// Address to listen on for runs.
+    ///
+    /// The agent runs any binary it receives, so it should only be reachable in trusted networks.
+    #[arg(long, default_value = "127.0.0.1:7500")]
+    pub listen: String,
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+pub struct DecodeCmdConfig {
+    /// Filepath to a TOML file that contains the runner configuration.
+    ///
+    /// Default: `.embedded/runner.toml`
+    #[arg(long)]
+    pub runner_cfg: Option<PathBuf>,
+    /// Filepath to the binary that was running while the RTT data was recorded.
+    #[arg(long)]
+    pub elf: PathBuf,
+    /// Optional name for the test run.
+    ///
+    /// Default: Relative filepath of the binary.
+    #[arg(long)]
+    pub run_name: Option<String>,
+    /// Optional path to a directory that is used to store test results and logs
+    ///
+    /// Default: Directory of the recorded RTT data.
+    #[arg(long)]
+    pub output_dir: Option<PathBuf>,
+    /// Path to look for custom JSON data that is linked with the test run.
+    ///
+    /// Default: `.embedded/test_run_data.json`
+    #[arg(long)]
+    pub data_filepath: Option<PathBuf>,
+    /// `true`: Writes a JUnit XML report `junit.xml` next to the coverage file.
+    ///
+    /// This setting overwrites the one optionally set in the runner configuration.
+    #[arg(long)]
+    pub junit: Option<bool>,
+    /// Filepath to the raw RTT data (e.g. `rtt.bin` in the output directory of a run).
+    pub rtt_data: PathBuf,
+}
+
 #[derive(Debug, Clone, clap::Parser)]
 pub struct CollectCmdConfig {
     pub output: Option<PathBuf>,
+    /// Optional filepath to write one combined JUnit XML report for all collected test runs.
+    #[arg(long)]
+    pub junit: Option<PathBuf>,
 }
 
 #[derive(Debug, Default, Clone, serde::Deserialize)]
@@ -107,12 +244,26 @@ pub struct RunnerConfig {
     pub load: Option<String>,
     #[serde(alias = "pre-exit")]
     pub pre_exit: Option<String>,
+    /// OpenOCD configuration file, passed before the files of the `[openocd]` section.
+    ///
+    /// Default: `.embedded/openocd.cfg` if the `[openocd]` section sets no files
     #[serde(alias = "openocd-cfg")]
     pub openocd_cfg: Option<PathBuf>,
+    /// Command line of OpenOCD.
+    pub openocd: Option<OpenOcdConfig>,
     #[serde(alias = "gdb-connection")]
     pub gdb_connection: Option<String>,
+    /// Serial number of the debug probe to use if several probes are attached.
+    ///
+    /// Default: The GDB server picks the probe
+    #[serde(alias = "probe-serial")]
+    pub probe_serial: Option<String>,
     #[serde(alias = "gdb-logfile")]
     pub gdb_logfile: Option<PathBuf>,
+    /// GDB that controls the target.
+    ///
+    /// Default: GDB picked for the architecture of the binary
+    pub gdb: Option<GdbConfig>,
     #[serde(alias = "pre-runner")]
     pub pre_runner: Option<Command>,
     #[serde(alias = "pre-runner-windows")]
@@ -121,10 +272,11 @@ pub struct RunnerConfig {
     pub post_runner: Option<Command>,
     #[serde(alias = "post-runner-windows")]
     pub post_runner_windows: Option<Command>,
+    /// Port of the RTT server on the host, or `auto` to use a free port for every run.
+    ///
+    /// Default: `19021`
     #[serde(alias = "rtt-port")]
-    pub rtt_port: Option<u16>,
-    #[serde(alias = "windows-sleep")]
-    pub windows_sleep: Option<bool>,
+    pub rtt_port: Option<RttPort>,
     #[serde(alias = "extern-coverage")]
     pub extern_coverage: Option<ExternCoverageConfig>,
     /// `true`: Uses RTT commands to communicate with SEGGER GDB instead of the `monitor rtt` commands from OpenOCD.
@@ -137,6 +289,386 @@ pub struct RunnerConfig {
     /// Default: `.embedded/test_run_data.json`
     #[serde(alias = "data-filepath", alias = "test-run-data-filepath")]
     pub data_filepath: Option<PathBuf>,
+    /// `true`: Writes a JUnit XML report `junit.xml` next to the coverage file.
+    ///
+    /// Default: `false`
+    #[serde(default)]
+    pub junit: bool,
+    /// Maximum duration in seconds to flash the binary and setup the RTT connection.
+    ///
+    /// Default: `60`
+    #[serde(alias = "setup-timeout")]
+    pub setup_timeout: Option<u64>,
+    /// Maximum duration in seconds of the test run after the RTT connection is established.
+    ///
+    /// Default: `3600`
+    #[serde(alias = "execution-timeout")]
+    pub execution_timeout: Option<u64>,
+    /// Maximum duration in seconds of one defmt test.
+    /// The run is stopped if a test neither finishes, nor the next test starts within this duration.
+    ///
+    /// Default: No timeout per test
+    #[serde(alias = "test-timeout")]
+    pub test_timeout: Option<u64>,
+    /// Regex pattern for defmt messages that signal the end of the run.
+    /// The run also ends on "all tests passed!" and on panics.
+    #[serde(alias = "completion-pattern")]
+    pub completion_pattern: Option<String>,
+    /// Settings that only apply to specific binaries.
+    ///
+    /// The key is the filename of the binary without the hash suffix added by Cargo.
+    #[serde(default)]
+    pub binaries: HashMap<String, BinaryConfig>,
+    /// Connection profiles of the boards attached to the host, selected per `--board`.
+    #[serde(default)]
+    pub boards: HashMap<String, BoardConfig>,
+    /// Backend that runs the binary.
+    ///
+    /// Default: `openocd`
+    #[serde(default)]
+    pub backend: BackendKind,
+    /// Settings for the QEMU backend.
+    pub qemu: Option<QemuConfig>,
+    /// Settings for the pyOCD backend.
+    pub pyocd: Option<PyOcdConfig>,
+    /// Starts the SEGGER J-Link GDB server for every run if `segger-gdb` is set.
+    ///
+    /// Default: The SEGGER GDB server at `gdb-connection` is used
+    pub jlink: Option<JLinkConfig>,
+    /// Starts OpenOCD once as background server that is reused by later runs.
+    ///
+    /// Default: OpenOCD is started by GDB for every run
+    #[serde(alias = "openocd-server")]
+    pub openocd_server: Option<OpenOcdServerConfig>,
+    /// Maximum duration in seconds to wait for a probe that is used by another run.
+    ///
+    /// Default: `600`
+    #[serde(alias = "probe-lock-timeout")]
+    pub probe_lock_timeout: Option<u64>,
+}
+
+/// GDB executable and its arguments.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+pub struct GdbConfig {
+    /// GDB executable.
+    ///
+    /// Default: First GDB of the candidates for the architecture of the binary that is found in `PATH`
+    pub path: Option<String>,
+    /// Additional arguments passed to GDB.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// GDB executables per architecture (`arm`, `aarch64`, `riscv`, `xtensa`) that are tried in order.
+    ///
+    /// Candidates set for an architecture replace its default candidates.
+    #[serde(default)]
+    pub candidates: HashMap<String, Vec<String>>,
+}
+
+/// GDB used if no better candidate is found.
+pub const DEFAULT_GDB: &str = "arm-none-eabi-gdb";
+
+/// GDB executables tried in order for the architecture of the binary.
+const DEFAULT_GDB_CANDIDATES: [(&str, &[&str]); 4] = [
+    ("arm", &[DEFAULT_GDB, "gdb-multiarch"]),
+    ("aarch64", &["aarch64-none-elf-gdb", "gdb-multiarch"]),
+    (
+        "riscv",
+        &[
+            "riscv32-esp-elf-gdb",
+            "riscv64-unknown-elf-gdb",
+            "riscv32-unknown-elf-gdb",
+            "gdb-multiarch",
+        ],
+    ),
+    (
+        "xtensa",
+        &[
+            "xtensa-esp-elf-gdb",
+            "xtensa-esp32-elf-gdb",
+            "xtensa-esp32s3-elf-gdb",
+            "xtensa-esp32s2-elf-gdb",
+        ],
+    ),
+];
+
+/// Port of the RTT server on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "RttPortSetting")]
+pub enum RttPort {
+    /// Port set in the configuration.
+    Fixed(u16),
+    /// Free port picked by the runner.
+    /// The RTT server must identify itself with the message passed on start.
+    Auto,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum RttPortSetting {
+    Port(u16),
+    Keyword(String),
+}
+
+impl TryFrom<RttPortSetting> for RttPort {
+    type Error = String;
+
+    fn try_from(value: RttPortSetting) -> Result<Self, Self::Error> {
+        match value {
+            RttPortSetting::Port(port) => Ok(RttPort::Fixed(port)),
+            RttPortSetting::Keyword(keyword) if keyword == "auto" => Ok(RttPort::Auto),
+            RttPortSetting::Keyword(keyword) => Err(format!(
+                "invalid RTT port '{keyword}', expected a port number or \"auto\""
+            )),
+        }
+    }
+}
+
+/// Command line of OpenOCD.
+///
+/// Files, search paths and commands are resolved like the `load` section.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+pub struct OpenOcdConfig {
+    /// OpenOCD executable.
+    /// The `OPENOCD` environment variable takes precedence.
+    ///
+    /// Default: `openocd`
+    pub executable: Option<String>,
+    /// Directories to search for configuration files, passed per `-s`.
+    #[serde(alias = "search-paths", default)]
+    pub search_paths: Vec<String>,
+    /// Configuration files passed per `-f` in the given order (e.g. `interface/stlink.cfg`, `target/stm32f4x.cfg`).
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Commands passed per `-c` after the configuration files (e.g. `adapter speed 4000`).
+    #[serde(default)]
+    pub commands: Vec<String>,
+}
+
+/// Default OpenOCD executable.
+pub const DEFAULT_OPENOCD: &str = "openocd";
+
+/// Resolved OpenOCD command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenOcdCommand {
+    pub executable: String,
+    /// Arguments for the search paths, configuration files and commands.
+    pub args: Vec<String>,
+}
+
+/// Ports of the OpenOCD background server.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+pub struct OpenOcdServerConfig {
+    /// Default: `3333`
+    #[serde(alias = "gdb-port")]
+    pub gdb_port: Option<u16>,
+    /// Default: `6666`
+    #[serde(alias = "tcl-port")]
+    pub tcl_port: Option<u16>,
+}
+
+/// Default GDB port of OpenOCD.
+pub const DEFAULT_OPENOCD_GDB_PORT: u16 = 3333;
+/// Default Tcl port of OpenOCD.
+pub const DEFAULT_OPENOCD_TCL_PORT: u16 = 6666;
+
+impl OpenOcdServerConfig {
+    pub fn gdb_port(&self) -> u16 {
+        self.gdb_port.unwrap_or(DEFAULT_OPENOCD_GDB_PORT)
+    }
+
+    pub fn tcl_port(&self) -> u16 {
+        self.tcl_port.unwrap_or(DEFAULT_OPENOCD_TCL_PORT)
+    }
+}
+
+/// Backends that may run the binary.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// OpenOCD started by GDB, or the GDB server set in `gdb-connection`.
+    #[default]
+    OpenOcd,
+    /// QEMU emulating the target.
+    Qemu,
+    /// GDB server of pyOCD started by the runner.
+    PyOcd,
+}
+
+/// Settings to emulate the target with QEMU.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct QemuConfig {
+    /// QEMU executable.
+    ///
+    /// Default: `qemu-system-arm`
+    pub executable: Option<String>,
+    /// Emulated machine (e.g. `lm3s6965evb`).
+    pub machine: String,
+    /// Emulated CPU (e.g. `cortex-m3`).
+    ///
+    /// Default: CPU of the machine
+    pub cpu: Option<String>,
+    /// Additional arguments passed to QEMU.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Port of the gdbstub of QEMU.
+    ///
+    /// Default: `1234`
+    #[serde(alias = "gdb-port")]
+    pub gdb_port: Option<u16>,
+    /// Way the target sends defmt frames.
+    ///
+    /// Default: `semihosting`
+    #[serde(default)]
+    pub transport: QemuTransport,
+    /// Port of the QEMU socket the defmt frames are read from.
+    ///
+    /// Default: `19021`
+    #[serde(alias = "log-port")]
+    pub log_port: Option<u16>,
+}
+
+/// Default port of the gdbstub of QEMU.
+pub const DEFAULT_QEMU_GDB_PORT: u16 = 1234;
+
+impl QemuConfig {
+    pub fn gdb_port(&self) -> u16 {
+        self.gdb_port.unwrap_or(DEFAULT_QEMU_GDB_PORT)
+    }
+
+    pub fn log_port(&self) -> u16 {
+        self.log_port.unwrap_or(crate::DEFAULT_RTT_PORT)
+    }
+}
+
+/// Settings to run the binary with the GDB server of pyOCD.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PyOcdConfig {
+    /// pyOCD executable.
+    ///
+    /// Default: `pyocd`
+    pub executable: Option<String>,
+    /// Target type of pyOCD (e.g. `stm32h743zitx`).
+    pub target: String,
+    /// CMSIS-Packs that contain the target, if it is not built into pyOCD.
+    #[serde(default)]
+    pub packs: Vec<PathBuf>,
+    /// Additional arguments passed to `pyocd gdbserver`.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Port of the GDB server of pyOCD.
+    ///
+    /// Default: `3333`
+    #[serde(alias = "gdb-port")]
+    pub gdb_port: Option<u16>,
+}
+
+impl PyOcdConfig {
+    pub fn gdb_port(&self) -> u16 {
+        self.gdb_port.unwrap_or(DEFAULT_PYOCD_GDB_PORT)
+    }
+}
+
+/// Default GDB port of pyOCD.
+pub const DEFAULT_PYOCD_GDB_PORT: u16 = 3333;
+
+/// Settings to start the SEGGER J-Link GDB server.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct JLinkConfig {
+    /// J-Link GDB server executable.
+    ///
+    /// Default: `JLinkGDBServerCLExe` (`JLinkGDBServerCL` on Windows)
+    pub executable: Option<String>,
+    /// Device name of the target (e.g. `STM32F407VG`).
+    pub device: String,
+    /// Target interface.
+    ///
+    /// Default: `SWD`
+    pub interface: Option<String>,
+    /// Interface speed in kHz.
+    ///
+    /// Default: `4000`
+    pub speed: Option<u32>,
+    /// Additional arguments passed to the J-Link GDB server.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Port of the J-Link GDB server.
+    /// The SWO and telnet ports of the server are the two ports after it.
+    ///
+    /// Default: `2331`
+    #[serde(alias = "gdb-port")]
+    pub gdb_port: Option<u16>,
+}
+
+/// Default GDB port of the J-Link GDB server.
+pub const DEFAULT_JLINK_GDB_PORT: u16 = 2331;
+
+impl JLinkConfig {
+    pub fn gdb_port(&self) -> u16 {
+        self.gdb_port.unwrap_or(DEFAULT_JLINK_GDB_PORT)
+    }
+
+    pub fn swo_port(&self) -> u16 {
+        self.gdb_port() + 1
+    }
+
+    pub fn telnet_port(&self) -> u16 {
+        self.gdb_port() + 2
+    }
+}
+
+/// Ways the target may send defmt frames when emulated by QEMU.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QemuTransport {
+    /// Semihosting output (e.g. from `defmt-semihosting`).
+    #[default]
+    Semihosting,
+    /// First serial port of the machine.
+    Serial,
+}
+
+/// Settings that overwrite the runner configuration for one binary.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+pub struct BinaryConfig {
+    #[serde(alias = "setup-timeout")]
+    pub setup_timeout: Option<u64>,
+    #[serde(alias = "execution-timeout")]
+    pub execution_timeout: Option<u64>,
+    #[serde(alias = "test-timeout")]
+    pub test_timeout: Option<u64>,
+}
+
+/// Connection profile of one board that overwrites the runner configuration.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+pub struct BoardConfig {
+    #[serde(alias = "openocd-cfg")]
+    pub openocd_cfg: Option<PathBuf>,
+    #[serde(alias = "gdb-connection")]
+    pub gdb_connection: Option<String>,
+    #[serde(alias = "probe-serial")]
+    pub probe_serial: Option<String>,
+    #[serde(alias = "rtt-port")]
+    pub rtt_port: Option<RttPort>,
+    #[serde(alias = "segger-gdb")]
+    pub segger_gdb: Option<bool>,
+    #[serde(alias = "openocd-server")]
+    pub openocd_server: Option<OpenOcdServerConfig>,
+    pub openocd: Option<OpenOcdConfig>,
+    pub backend: Option<BackendKind>,
+    pub qemu: Option<QemuConfig>,
+    pub pyocd: Option<PyOcdConfig>,
+    pub jlink: Option<JLinkConfig>,
+}
+
+/// Timeouts for the phases of one test run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Maximum duration to flash the binary and setup the RTT connection.
+    pub setup: Duration,
+    /// Maximum duration of the test run after the RTT connection is established.
+    pub execution: Duration,
+    /// Maximum duration of one defmt test.
+    pub test: Option<Duration>,
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -158,117 +690,322 @@ pub struct Command {
 pub enum CfgError {
     #[error("Could not find rtt block in binary. Cause: {}", .0)]
     FindingRttBlock(String),
+    #[error("Could not read the architecture of the binary. Cause: {}", .0)]
+    ReadingArchitecture(String),
     #[error("Could not build the template context. Cause: {}", .0)]
     BuildingTemplateContext(String),
     #[error("Could not resolve the load section. Cause: {}", .0)]
     ResolvingLoad(String),
     #[error("Could not resolve the pre-exit section. Cause: {}", .0)]
     ResolvingPreExit(String),
+    #[error("Could not resolve the openocd section. Cause: {}", .0)]
+    ResolvingOpenOcd(String),
+    #[error("Backend `qemu` requires a `[qemu]` section with at least the `machine` setting.")]
+    MissingQemu,
+    #[error("Backend `pyocd` requires a `[pyocd]` section with at least the `target` setting.")]
+    MissingPyOcd,
+    #[error(
+        "`segger-gdb` requires a `[jlink]` section, or `gdb-connection` set to the address of a running SEGGER GDB server."
+    )]
+    MissingGdbConnection,
+    #[error("`rtt-port = \"auto\"` is not supported with `segger-gdb` and `gdb-connection`, because the SEGGER GDB server sets the RTT port.")]
+    AutoRttPortUnsupported,
 }
 
 impl RunnerConfig {
-    pub fn gdb_script(
+    /// Returns the settings set for the given binary.
+    ///
+    /// Binaries built by Cargo for tests have a hash suffix (e.g. `my_test-0123456789abcdef`),
+    /// which is ignored if there are no settings for the full filename.
+    pub fn binary_cfg(&self, binary: &Path) -> Option<&BinaryConfig> {
+        let filename = binary.file_stem()?.to_str()?;
+
+        self.binaries.get(filename).or_else(|| {
+            let (name, hash) = filename.rsplit_once('-')?;
+            if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                self.binaries.get(name)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Overwrites the connection settings with the ones set for the board.
+    pub fn apply_board(&mut self, board_cfg: &BoardConfig) {
+        let board_cfg = board_cfg.clone();
+
+        self.openocd_cfg = board_cfg.openocd_cfg.or(self.openocd_cfg.take());
+        self.openocd = board_cfg.openocd.or(self.openocd.take());
+        self.gdb_connection = board_cfg.gdb_connection.or(self.gdb_connection.take());
+        self.probe_serial = board_cfg.probe_serial.or(self.probe_serial.take());
+        self.rtt_port = board_cfg.rtt_port.or(self.rtt_port);
+        self.segger_gdb = board_cfg.segger_gdb.unwrap_or(self.segger_gdb);
+        self.openocd_server = board_cfg.openocd_server.or(self.openocd_server.take());
+        self.backend = board_cfg.backend.unwrap_or(self.backend);
+        self.qemu = board_cfg.qemu.or(self.qemu.take());
+        self.pyocd = board_cfg.pyocd.or(self.pyocd.take());
+        self.jlink = board_cfg.jlink.or(self.jlink.take());
+    }
+
+    /// Resolves the conditions to end reading defmt frames.
+    pub fn end_conditions(
         &self,
-        binary: &Path,
-        output_dir: &Path,
-        segger_gdb: bool,
-    ) -> Result<String, CfgError> {
-        let context = build_template_context(binary)?;
-        let resolved_load = if let Some(load) = &self.load {
-            Tera::one_off(load, &context, false)
-                .map_err(|err| CfgError::ResolvingLoad(err.to_string()))?
-        } else {
-            "load".to_string()
-        };
-        let (rtt_address, rtt_length) = find_rtt_block(binary)?;
+        timeouts: &Timeouts,
+        run_cfg: &RunCmdConfig,
+    ) -> Result<EndConditions, ConfigError> {
+        let completion_pattern = run_cfg
+            .completion_pattern
+            .as_ref()
+            .or(self.completion_pattern.as_ref())
+            .map(|pattern| regex::Regex::new(pattern))
+            .transpose()
+            .map_err(ConfigError::CompletionPattern)?;
 
-        #[cfg(target_os = "windows")]
-        let sleep_cmd = "timeout";
-        #[cfg(not(target_os = "windows"))]
-        let sleep_cmd = "sleep";
+        Ok(EndConditions {
+            test_timeout: timeouts.test,
+            completion_pattern,
+        })
+    }
 
-        let sleep_cmd = if self.windows_sleep == Some(true) {
-            "sleep"
-        } else {
-            sleep_cmd
-        };
+    /// Resolves the timeouts for the given binary.
+    ///
+    /// Timeouts set per CLI take precedence over the ones set for the binary,
+    /// which take precedence over the general ones in the runner configuration.
+    pub fn timeouts(&self, binary: &Path, run_cfg: &RunCmdConfig) -> Timeouts {
+        let binary_cfg = self.binary_cfg(binary);
 
-        let gdb_logfile = self
-            .gdb_logfile
-            .clone()
-            .unwrap_or(output_dir.join("gdb.log"));
-        let gdb_logfile = gdb_logfile
-            .to_slash()
-            .expect("GDB logfile must be a valid filepath.");
+        let setup = run_cfg
+            .setup_timeout
+            .or(binary_cfg.and_then(|cfg| cfg.setup_timeout))
+            .or(self.setup_timeout)
+            .unwrap_or(crate::SETUP_RTT_TIMEOUT_SEC);
+        let execution = run_cfg
+            .execution_timeout
+            .or(binary_cfg.and_then(|cfg| cfg.execution_timeout))
+            .or(self.execution_timeout)
+            .unwrap_or(crate::EXECUTION_TIMEOUT_SEC);
+        let test = run_cfg
+            .test_timeout
+            .or(binary_cfg.and_then(|cfg| cfg.test_timeout))
+            .or(self.test_timeout);
 
-        let gdb_conn = if let Some(gdb_conn) = &self.gdb_connection {
-            format!("target extended-remote {gdb_conn}\nset logging file {gdb_logfile}")
-        } else {
-            let openocd_cfg = self
-                .openocd_cfg
-                .clone()
-                .unwrap_or(PathBuf::from(".embedded/openocd.cfg"));
-            let openocd_cfg = openocd_cfg
-                .to_slash()
-                .expect("OpenOCD configuration file must be a valid filepath.");
-            format!("target extended-remote | openocd -c \"gdb_port pipe; log_output {gdb_logfile}\" -f {openocd_cfg}")
-        };
+        Timeouts {
+            setup: Duration::from_secs(setup),
+            execution: Duration::from_secs(execution),
+            test: test.map(Duration::from_secs),
+        }
+    }
 
-        let rtt_section = if segger_gdb {
-            format!(
-                "
-monitor exec SetRTTSearchRanges 0x{rtt_address:x} 0x{rtt_length:x}
-monitor exec SetRTTChannel 0
-            "
-            )
-        } else {
-            format!(
-                "
-monitor rtt setup 0x{:x} {} \"SEGGER RTT\"
-monitor rtt start
-monitor rtt server start {} 0
-            ",
-                rtt_address,
-                rtt_length,
-                self.rtt_port.unwrap_or(super::DEFAULT_RTT_PORT)
-            )
-        };
+    /// Returns the settings for the QEMU backend.
+    pub fn qemu_cfg(&self) -> Result<&QemuConfig, CfgError> {
+        self.qemu.as_ref().ok_or(CfgError::MissingQemu)
+    }
 
-        let pre_exit_section = if let Some(pre_exit_template) = &self.pre_exit {
-            Tera::one_off(pre_exit_template, &context, false)
-                .map_err(|err| CfgError::ResolvingPreExit(err.to_string()))?
-        } else {
-            String::new()
+    /// Returns the settings for the pyOCD backend.
+    pub fn pyocd_cfg(&self) -> Result<&PyOcdConfig, CfgError> {
+        self.pyocd.as_ref().ok_or(CfgError::MissingPyOcd)
+    }
+
+    /// Returns the key that identifies the probe used for the run.
+    ///
+    /// Returns `None` if no probe is used, because the target is emulated.
+    pub fn probe_key(&self) -> Option<String> {
+        if self.backend == BackendKind::Qemu {
+            return None;
+        }
+
+        match (&self.probe_serial, &self.gdb_connection) {
+            (Some(serial), _) => Some(serial.clone()),
+            // pyOCD picks the only attached probe
+            (None, _) if self.backend == BackendKind::PyOcd => Some("pyocd".to_string()),
+            // J-Link GDB server started by the runner picks the only attached probe
+            (None, None) if self.segger_gdb => Some("jlink".to_string()),
+            (None, Some(connection)) => Some(connection.clone()),
+            (None, None) => Some(self.openocd_files().join(" ")),
+        }
+    }
+
+    /// Returns the maximum duration to wait for a probe that is used by another run.
+    pub fn probe_lock_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.probe_lock_timeout
+                .unwrap_or(crate::PROBE_LOCK_TIMEOUT_SEC),
+        )
+    }
+
+    /// Returns the port of the RTT server started by the GDB server.
+    pub fn rtt_server_port(&self) -> RttPort {
+        self.rtt_port
+            .unwrap_or(RttPort::Fixed(crate::DEFAULT_RTT_PORT))
+    }
+
+    /// Resolves the GDB executable for the given binary.
+    ///
+    /// The `GDB` environment variable takes precedence over the `gdb` section.
+    /// Without a GDB set, the first candidate for the architecture of the binary that is found in `PATH` is used.
+    pub fn gdb_executable(&self, binary: &Path) -> String {
+        if let Ok(gdb) = std::env::var("GDB") {
+            return gdb;
+        }
+        if let Some(path) = self.gdb.as_ref().and_then(|gdb| gdb.path.clone()) {
+            return path;
+        }
+
+        let arch = match binary_architecture(binary) {
+            Ok(arch) => arch,
+            Err(err) => {
+                log::debug!("Using '{DEFAULT_GDB}'. Cause: {err}");
+                return DEFAULT_GDB.to_string();
+            }
         };
+        let candidates = self.gdb_candidates(arch);
+        let path_var = std::env::var_os("PATH").unwrap_or_default();
 
-        Ok(format!(
-            "
-set pagination off
+        match candidates
+            .iter()
+            .find(|gdb| find_executable(gdb, &path_var).is_some())
+        {
+            Some(gdb) => gdb.clone(),
+            None => {
+                log::warn!(
+                    "None of the GDB executables for '{arch}' binaries found in `PATH`: {}",
+                    candidates.join(", ")
+                );
+                candidates
+                    .first()
+                    .cloned()
+                    .unwrap_or(DEFAULT_GDB.to_string())
+            }
+        }
+    }
+
+    /// Returns the additional arguments passed to GDB.
+    pub fn gdb_args(&self) -> &[String] {
+        self.gdb
+            .as_ref()
+            .map(|gdb| gdb.args.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns the GDB executables to try for the given architecture.
+    pub fn gdb_candidates(&self, arch: &str) -> Vec<String> {
+        if let Some(candidates) = self.gdb.as_ref().and_then(|gdb| gdb.candidates.get(arch)) {
+            return candidates.clone();
+        }
+
+        DEFAULT_GDB_CANDIDATES
+            .iter()
+            .find(|(name, _)| *name == arch)
+            .map(|(_, candidates)| candidates.iter().map(|gdb| gdb.to_string()).collect())
+            .unwrap_or(vec!["gdb-multiarch".to_string()])
+    }
+
+    /// Returns the logfile for GDB, and for the GDB server if GDB starts it.
+    pub fn gdb_logfile(&self, output_dir: &Path) -> String {
+        self.gdb_logfile
+            .clone()
+            .unwrap_or(output_dir.join("gdb.log"))
+            .to_slash()
+            .expect("GDB logfile must be a valid filepath.")
+            .into_owned()
+    }
+
+    /// Resolves the `load` section for the given binary.
+    ///
+    /// The given default commands are used if no `load` section is set.
+    pub fn load_commands(&self, binary: &Path, default: &str) -> Result<Vec<String>, CfgError> {
+        let resolved_load = match &self.load {
+            Some(load) => {
+                let context = build_template_context(binary)?;
+                Tera::one_off(load, &context, false)
+                    .map_err(|err| CfgError::ResolvingLoad(err.to_string()))?
+            }
+            None => default.to_string(),
+        };
 
-{gdb_conn}
+        Ok(command_lines(&resolved_load))
+    }
 
-{resolved_load}
+    /// Returns the OpenOCD configuration files before they are resolved.
+    pub fn openocd_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .openocd_cfg
+            .iter()
+            .map(|file| {
+                file.to_slash()
+                    .expect("OpenOCD configuration file must be a valid filepath.")
+                    .into_owned()
+            })
+            .collect();
+        if let Some(openocd) = &self.openocd {
+            files.extend(openocd.files.iter().cloned());
+        }
 
-b main
-continue
+        if files.is_empty() {
+            files.push(".embedded/openocd.cfg".to_string());
+        }
+        files
+    }
 
-{rtt_section}
+    /// Resolves the OpenOCD command line for the given binary.
+    ///
+    /// Commands to set up the GDB and RTT servers are not included.
+    pub fn openocd_command(&self, binary: &Path) -> Result<OpenOcdCommand, CfgError> {
+        let openocd = self.openocd.clone().unwrap_or_default();
+        let executable = std::env::var("OPENOCD")
+            .ok()
+            .or(openocd.executable)
+            .unwrap_or(DEFAULT_OPENOCD.to_string());
 
-shell {sleep_cmd} 1
+        let context = build_template_context(binary)?;
+        let resolve = |template: &str| {
+            Tera::one_off(template, &context, false)
+                .map_err(|err| CfgError::ResolvingOpenOcd(err.to_string()))
+        };
 
-continue
+        let mut args = Vec::new();
+        for search_path in &openocd.search_paths {
+            args.push("-s".to_string());
+            args.push(resolve(search_path)?);
+        }
+        for file in self.openocd_files() {
+            args.push("-f".to_string());
+            args.push(resolve(&file)?);
+        }
+        for command in &openocd.commands {
+            args.push("-c".to_string());
+            args.push(resolve(command)?);
+        }
 
-shell {sleep_cmd} 1
+        Ok(OpenOcdCommand { executable, args })
+    }
 
-{pre_exit_section}
+    /// Resolves the `pre-exit` section for the given binary.
+    pub fn pre_exit_commands(&self, binary: &Path) -> Result<Vec<String>, CfgError> {
+        let pre_exit = match &self.pre_exit {
+            Some(pre_exit_template) => {
+                let context = build_template_context(binary)?;
+                Tera::one_off(pre_exit_template, &context, false)
+                    .map_err(|err| CfgError::ResolvingPreExit(err.to_string()))?
+            }
+            None => String::new(),
+        };
 
-quit        
-"
-        ))
+        Ok(command_lines(&pre_exit))
     }
 }
 
-fn find_rtt_block(binary: &Path) -> Result<(u64, u64), CfgError> {
+/// Splits the given section into GDB commands, ignoring empty lines and comments.
+fn command_lines(section: &str) -> Vec<String> {
+    section
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+pub(crate) fn find_rtt_block(binary: &Path) -> Result<(u64, u64), CfgError> {
     let data = std::fs::read(binary).map_err(|err| {
         CfgError::FindingRttBlock(format!("Could not read binary file. Cause: {err}"))
     })?;
@@ -287,6 +1024,44 @@ fn find_rtt_block(binary: &Path) -> Result<(u64, u64), CfgError> {
     ))
 }
 
+/// Returns the name of the architecture the binary is built for, as used for `gdb.candidates`.
+pub(crate) fn binary_architecture(binary: &Path) -> Result<&'static str, CfgError> {
+    let data = std::fs::read(binary).map_err(|err| {
+        CfgError::ReadingArchitecture(format!("Could not read binary file. Cause: {err}"))
+    })?;
+    let file = object::File::parse(&*data).map_err(|err| {
+        CfgError::ReadingArchitecture(format!("Could not parse binary file. Cause: {err}"))
+    })?;
+
+    Ok(match file.architecture() {
+        object::Architecture::Arm => "arm",
+        object::Architecture::Aarch64 => "aarch64",
+        object::Architecture::Riscv32 | object::Architecture::Riscv64 => "riscv",
+        object::Architecture::Xtensa => "xtensa",
+        _ => "unknown",
+    })
+}
+
+/// Returns the filepath of the executable with the given name in one of the directories of `path_var`.
+///
+/// Names that are filepaths are returned if the file exists.
+fn find_executable(name: &str, path_var: &std::ffi::OsStr) -> Option<PathBuf> {
+    let is_file = |path: PathBuf| {
+        let path = if cfg!(windows) && path.extension().is_none() {
+            path.with_extension("exe")
+        } else {
+            path
+        };
+        path.is_file().then_some(path)
+    };
+
+    if Path::new(name).components().count() > 1 {
+        return is_file(PathBuf::from(name));
+    }
+
+    std::env::split_paths(path_var).find_map(|dir| is_file(dir.join(name)))
+}
+
 fn build_template_context(binary: &Path) -> Result<Context, CfgError> {
     let mut context = Context::new();
     let parent = binary.parent().map(|p| p.to_path_buf()).unwrap_or_default();
@@ -324,9 +1099,96 @@ fn build_template_context(binary: &Path) -> Result<Context, CfgError> {
 mod test {
     use std::path::PathBuf;
 
-    use crate::cfg::build_template_context;
+    use clap::Parser;
+
+    use crate::cfg::{
+        build_template_context, BackendKind, ConfigError, ResolvedConfig, RttPort, RunCmdConfig,
+        RunnerConfig,
+    };
+
+    use super::{binary_architecture, find_executable, find_rtt_block};
+
+    #[test]
+    fn rtt_port_setting() {
+        let fixed: RunnerConfig = toml::from_str("rtt-port = 19022").unwrap();
+        assert_eq!(fixed.rtt_server_port(), RttPort::Fixed(19022));
+
+        let auto: RunnerConfig = toml::from_str("rtt-port = \"auto\"").unwrap();
+        assert_eq!(auto.rtt_server_port(), RttPort::Auto);
+
+        assert!(
+            toml::from_str::<RunnerConfig>("rtt-port = \"any\"").is_err(),
+            "Invalid RTT port accepted."
+        );
+    }
+
+    #[test]
+    fn board_profile() {
+        let runner_cfg: RunnerConfig = toml::from_str(
+            r#"
+            openocd-cfg = ".embedded/nucleo.cfg"
+            rtt-port = 19022
+
+            [boards.nucleo-2]
+            probe-serial = "0670FF"
+
+            [boards.lpc55]
+            backend = "pyocd"
+            probe-serial = "0671FF"
+            pyocd = { target = "lpc55s69" }
+            "#,
+        )
+        .unwrap();
+        let main_cfg = ResolvedConfig {
+            runner_cfg,
+            verbose: false,
+            workspace_dir: PathBuf::from("."),
+            embedded_dir: PathBuf::from(".embedded"),
+        };
+        let mut run_cfg = RunCmdConfig::parse_from([
+            "run",
+            "--board",
+            "nucleo-2",
+            "target/thumbv7em-none-eabihf/debug/test",
+        ]);
+
+        let board_cfg = main_cfg.for_run(&run_cfg).unwrap().runner_cfg;
+        assert_eq!(board_cfg.probe_serial.as_deref(), Some("0670FF"));
+        assert_eq!(
+            board_cfg.openocd_cfg,
+            Some(PathBuf::from(".embedded/nucleo.cfg")),
+            "General setting not kept."
+        );
+        assert_eq!(board_cfg.probe_key().as_deref(), Some("0670FF"));
+
+        run_cfg.probe_serial = Some("0671FF".to_string());
+        assert_eq!(
+            main_cfg
+                .for_run(&run_cfg)
+                .unwrap()
+                .runner_cfg
+                .probe_serial
+                .as_deref(),
+            Some("0671FF"),
+            "CLI setting not preferred."
+        );
 
-    use super::find_rtt_block;
+        run_cfg.board = Some("lpc55".to_string());
+        run_cfg.probe_serial = None;
+        let board_cfg = main_cfg.for_run(&run_cfg).unwrap().runner_cfg;
+        assert_eq!(board_cfg.backend, BackendKind::PyOcd);
+        assert_eq!(
+            board_cfg.pyocd_cfg().unwrap().target,
+            "lpc55s69",
+            "Backend settings of the board not applied."
+        );
+
+        run_cfg.board = Some("nucleo-3".to_string());
+        assert!(matches!(
+            main_cfg.for_run(&run_cfg),
+            Err(ConfigError::UnknownBoard(_))
+        ));
+    }
 
     #[test]
     fn load_template() {
@@ -353,6 +1215,32 @@ file \"{{ binary_filepath }}\"";
         );
     }
 
+    #[test]
+    fn binary_cfg_ignores_cargo_hash() {
+        let runner_cfg: RunnerConfig = toml::from_str(
+            "
+            execution-timeout = 10
+
+            [binaries.soak]
+            execution-timeout = 86400
+            ",
+        )
+        .unwrap();
+
+        assert!(
+            runner_cfg
+                .binary_cfg(&PathBuf::from("target/debug/deps/soak-0123456789abcdef"))
+                .is_some(),
+            "Hash suffix not ignored."
+        );
+        assert!(
+            runner_cfg
+                .binary_cfg(&PathBuf::from("target/debug/deps/soak-test"))
+                .is_none(),
+            "Non-hash suffix ignored."
+        );
+    }
+
     #[test]
     fn rtt_block_in_binary() {
         let binary = PathBuf::from("test_binaries/emb-runner-test");
@@ -361,4 +1249,94 @@ file \"{{ binary_filepath }}\"";
         dbg!(address);
         dbg!(size);
     }
+
+    #[test]
+    fn gdb_for_architecture() {
+        assert_eq!(
+            binary_architecture(&PathBuf::from("test_binaries/emb-runner-test")).unwrap(),
+            "arm"
+        );
+
+        let runner_cfg: RunnerConfig = toml::from_str(
+            "
+            gdb = { args = [\"-nx\"], candidates = { riscv = [\"riscv32-esp-elf-gdb\"] } }
+            ",
+        )
+        .unwrap();
+        assert_eq!(runner_cfg.gdb_args(), ["-nx"]);
+        assert_eq!(
+            runner_cfg.gdb_candidates("riscv"),
+            ["riscv32-esp-elf-gdb"],
+            "Configured candidates not used."
+        );
+        assert_eq!(
+            runner_cfg.gdb_candidates("arm"),
+            ["arm-none-eabi-gdb", "gdb-multiarch"],
+            "Default candidates not used."
+        );
+
+        let bin_dir = std::env::temp_dir().join("emb-runner-gdb-path");
+        std::fs::create_dir_all(&bin_dir).unwrap();
+        let gdb = if cfg!(windows) {
+            "riscv32-esp-elf-gdb.exe"
+        } else {
+            "riscv32-esp-elf-gdb"
+        };
+        std::fs::write(bin_dir.join(gdb), "").unwrap();
+        let path_var =
+            std::env::join_paths([PathBuf::from("/nonexistent"), bin_dir.clone()]).unwrap();
+
+        assert_eq!(
+            find_executable("riscv32-esp-elf-gdb", &path_var),
+            Some(bin_dir.join(gdb))
+        );
+        assert_eq!(find_executable("gdb-multiarch", &path_var), None);
+    }
+
+    #[test]
+    fn openocd_command_line() {
+        let runner_cfg: RunnerConfig = toml::from_str(
+            "
+            openocd-cfg = \".embedded/board.cfg\"
+
+            [openocd]
+            executable = \"/opt/openocd/bin/openocd\"
+            search-paths = [\"/opt/openocd/share/openocd/scripts\"]
+            files = [\"interface/stlink.cfg\", \"target/stm32f4x.cfg\"]
+            commands = [\"adapter speed 4000\", \"program {{ binary_filepath }} verify\"]
+            ",
+        )
+        .unwrap();
+
+        let openocd = runner_cfg
+            .openocd_command(&PathBuf::from("target/emb-test"))
+            .unwrap();
+
+        assert_eq!(
+            openocd.args,
+            [
+                "-s",
+                "/opt/openocd/share/openocd/scripts",
+                "-f",
+                ".embedded/board.cfg",
+                "-f",
+                "interface/stlink.cfg",
+                "-f",
+                "target/stm32f4x.cfg",
+                "-c",
+                "adapter speed 4000",
+                "-c",
+                "program target/emb-test verify",
+            ]
+        );
+        if std::env::var("OPENOCD").is_err() {
+            assert_eq!(openocd.executable, "/opt/openocd/bin/openocd");
+        }
+
+        assert_eq!(
+            RunnerConfig::default().openocd_files(),
+            [".embedded/openocd.cfg"],
+            "Default configuration file not used."
+        );
+    }
 }
@@ -59,6 +59,18 @@ pub async fn run(cfg: CollectCmdConfig) -> Result<(), RunnerError> {
         None => PathBuf::from("coverage.json"),
     };
 
+    if let Some(junit_file) = &cfg.junit {
+        tokio::fs::write(junit_file, crate::junit::junit_from_coverage(&coverages))
+            .await
+            .map_err(|err| {
+                RunnerError::Setup(format!(
+                    "Could not write combined JUnit report '{}'. Cause: {}",
+                    junit_file.display(),
+                    err
+                ))
+            })?;
+    }
+
     let combined_coverage =
         serde_json::to_string(&coverages).expect("Serializing coverage schema.");
 
@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     path::PathBuf,
 };
 
@@ -12,6 +12,8 @@ use mantra_schema::{
 use regex::Regex;
 use time::OffsetDateTime;
 
+use crate::sink::{FrameSink, SinkError};
+
 #[derive(Debug, thiserror::Error)]
 pub enum CoverageError {
     #[error("{}", .0)]
@@ -36,49 +38,104 @@ pub fn coverages_filepath() -> PathBuf {
     )
 }
 
-pub fn coverage_from_defmt_frames(
-    run_name: String,
-    data: Option<serde_json::Value>,
-    frames: &[DefmtFrame],
-    logs: Option<String>,
-) -> Result<CoverageSchema, CoverageError> {
-    if frames.is_empty() {
-        return Err(CoverageError::NoTests);
+/// Maximum number of frames kept per failed test in the logs of the test run.
+const MAX_TEST_LOG_FRAMES: usize = 1000;
+
+/// Builds the test coverage incrementally while defmt frames arrive.
+///
+/// Only the logs of failed tests are kept, so memory stays flat during long runs.
+#[derive(Debug, Default)]
+pub struct CoverageBuilder {
+    /// Host timestamp of the first received frame.
+    first_timestamp: Option<i64>,
+    nr_of_tests: u32,
+    tests: Vec<Test>,
+    current_test: Option<Test>,
+    covered_traces: HashMap<PathBuf, HashMap<Line, HashSet<ReqId>>>,
+    failures: HashMap<String, TestFailure>,
+    /// Test-fn entry, and the latest frames of the running test.
+    current_logs: Option<(DefmtFrame, VecDeque<DefmtFrame>)>,
+    /// Test-fn entries and frames of all failed tests.
+    failed_logs: Vec<DefmtFrame>,
+    /// First error that occured while building the coverage.
+    error: Option<CoverageError>,
+}
+
+impl CoverageBuilder {
+    /// Adds the given frame to the coverage.
+    ///
+    /// After an error, all further frames are ignored, and the error is returned by [`Self::build`].
+    pub fn add_frame(&mut self, frame: &DefmtFrame) {
+        if self.error.is_some() {
+            return;
+        }
+
+        self.first_timestamp.get_or_insert(frame.host_timestamp);
+
+        if let Err(err) = self.handle_frame(frame) {
+            self.error = Some(err);
+        }
     }
 
-    let timestamp = frames
-        .first()
-        .expect("At least one frame must be available.")
-        .host_timestamp;
-    let date = OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128).map_err(|_| {
-        CoverageError::BadDate(format!("Timestamp '{timestamp}' is not a valid date."))
-    })?;
+    /// Returns the name of the test that was started last, but did not finish.
+    pub fn unfinished_test(&self) -> Option<&str> {
+        self.current_test.as_ref().map(|test| test.name.as_str())
+    }
 
-    let test_fn_matcher = TEST_FN_MATCHER.get_or_init(|| {
-        Regex::new(
-            r"^\(\d+/(?<nr_tests>\d+)\)\s(?<state>(?:running)|(?:ignoring))\s`(?<fn_name>.+)`...",
-        )
-        .expect("Could not create regex matcher for defmt test-fn entries.")
-    });
-
-    let mut test_run = TestRun {
-        name: run_name,
-        date,
-        data,
-        logs,
-        tests: Vec::new(),
-        nr_of_tests: 0,
-    };
-    let mut current_test: Option<Test> = None;
-    let mut covered_traces: HashMap<PathBuf, HashMap<Line, HashSet<ReqId>>> = HashMap::new();
+    /// Panic and error messages of the running and all failed tests, using the test name as key.
+    pub fn failures(&self) -> &HashMap<String, TestFailure> {
+        &self.failures
+    }
 
-    for frame in frames {
-        if let Some(captured_test_fn) = test_fn_matcher.captures(&frame.data) {
-            if let Some(mut test) = current_test.take() {
-                test.state = TestState::Passed;
-                test.covered_files = drain_covered_traces(&mut covered_traces);
-                test_run.tests.push(test);
-            }
+    /// Sets the failure reason for the given test, overwriting any reason taken from the logs.
+    pub fn set_failure_reason(&mut self, test_name: &str, reason: String) {
+        self.failures
+            .entry(test_name.to_string())
+            .or_default()
+            .reason = Some(reason);
+    }
+
+    /// Creates the coverage of all added frames.
+    ///
+    /// A test that did not finish is marked as failed, because the target aborted during the test (e.g. panic).
+    pub fn build(
+        mut self,
+        run_name: String,
+        data: Option<serde_json::Value>,
+    ) -> Result<CoverageSchema, CoverageError> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let Some(timestamp) = self.first_timestamp else {
+            return Err(CoverageError::NoTests);
+        };
+        let date = OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128).map_err(|_| {
+            CoverageError::BadDate(format!("Timestamp '{timestamp}' is not a valid date."))
+        })?;
+
+        self.finish_test(TestState::Failed);
+
+        let logs = (!self.failed_logs.is_empty()).then(|| {
+            serde_json::to_string(&self.failed_logs).expect("DefmtFrames were deserialized before.")
+        });
+
+        Ok(CoverageSchema {
+            version: Some(mantra_schema::SCHEMA_VERSION.to_string()),
+            test_runs: vec![TestRun {
+                name: run_name,
+                date,
+                data,
+                logs,
+                tests: self.tests,
+                nr_of_tests: self.nr_of_tests,
+            }],
+        })
+    }
+
+    fn handle_frame(&mut self, frame: &DefmtFrame) -> Result<(), CoverageError> {
+        if let Some(captured_test_fn) = test_fn_matcher().captures(&frame.data) {
+            self.finish_test(TestState::Passed);
 
             let nr_tests: u32 = captured_test_fn
                 .name("nr_tests")
@@ -87,7 +144,7 @@ pub fn coverage_from_defmt_frames(
                 .parse()
                 .expect("Number of tests must be convertible to u32.");
 
-            test_run.nr_of_tests = nr_tests;
+            self.nr_of_tests = nr_tests;
 
             let fn_state = captured_test_fn
                 .name("state")
@@ -108,58 +165,149 @@ pub fn coverage_from_defmt_frames(
                     frame.data
                 )));
             };
-            let Some(mod_path) = &frame.location.module_path else {
-                return Err(CoverageError::Match(format!(
-                    "Missing line location information for log entry '{}'.",
-                    frame.data
-                )));
-            };
-            let mod_path_str = format!(
-                "{}{}",
-                mod_path.crate_name,
-                if mod_path.modules.is_empty() {
-                    String::new()
-                } else {
-                    format!("::{}", mod_path.modules.join("::"))
-                }
-            );
-
-            let test_fn_name = format!("{}::{}", mod_path_str, fn_name.as_str());
+            let test_fn_name = test_fn_name(frame, fn_name.as_str())?;
 
             match fn_state.as_str() {
                 "running" => {
-                    current_test = Some(Test { name: test_fn_name, filepath: PathBuf::from(file), line: line_nr, state: TestState::Failed, covered_files: Vec::new() });
+                    self.current_test = Some(Test { name: test_fn_name, filepath: PathBuf::from(file), line: line_nr, state: TestState::Failed, covered_files: Vec::new() });
+                    self.current_logs = Some((frame.clone(), VecDeque::new()));
                 }
                 "ignoring" => {
-                    test_run.tests.push(Test{ name: test_fn_name, filepath: PathBuf::from(file), line: line_nr, state: TestState::Skipped { reason: None }, covered_files: Vec::new() });
+                    self.tests.push(Test{ name: test_fn_name, filepath: PathBuf::from(file), line: line_nr, state: TestState::Skipped { reason: None }, covered_files: Vec::new() });
 
-                    debug_assert_eq!(current_test, None, "Open test state for ignored test.");
-                    debug_assert!(covered_traces.is_empty(), "Covered traces for ignored test.");
+                    debug_assert!(self.covered_traces.is_empty(), "Covered traces for ignored test.");
                 }
                 _ => unreachable!("Invalid state '{}' for test function '{}' in log entry '{}'. Only 'running' and 'ignoring' are allowed.", fn_state.as_str(), fn_name.as_str(), frame.data),
             }
         } else if let Some(covered_req) =
             mantra_rust_macros::extract::extract_first_coverage(&frame.data)
         {
-            covered_traces
+            self.covered_traces
                 .entry(covered_req.file)
                 .or_default()
                 .entry(covered_req.line)
                 .or_default()
                 .insert(covered_req.id);
         } else if frame.data == "all tests passed!" {
-            if let Some(mut test) = current_test.take() {
-                test.state = TestState::Passed;
-                test.covered_files = drain_covered_traces(&mut covered_traces);
-                test_run.tests.push(test);
+            self.finish_test(TestState::Passed);
+        } else if let Some(test) = &self.current_test {
+            let is_panic = frame.data.starts_with(PANIC_PREFIX);
+
+            if is_panic || frame.level == Some(log::Level::Error) {
+                let failure = self.failures.entry(test.name.clone()).or_default();
+
+                if is_panic && failure.reason.is_none() {
+                    failure.reason = Some(frame.data.clone());
+                }
+                failure.messages.push(frame.data.clone());
+            }
+
+            if let Some((_, logs)) = &mut self.current_logs {
+                if logs.len() == MAX_TEST_LOG_FRAMES {
+                    logs.pop_front();
+                }
+                logs.push_back(frame.clone());
             }
         }
+
+        Ok(())
     }
 
-    Ok(CoverageSchema {
-        version: Some(mantra_schema::SCHEMA_VERSION.to_string()),
-        test_runs: vec![test_run],
-    })
+    fn finish_test(&mut self, state: TestState) {
+        let Some(mut test) = self.current_test.take() else {
+            return;
+        };
+        let logs = self.current_logs.take();
+
+        if state == TestState::Failed {
+            if let Some((entry, logs)) = logs {
+                self.failed_logs.push(entry);
+                self.failed_logs.extend(logs);
+            }
+        } else {
+            self.failures.remove(&test.name);
+        }
+
+        test.state = state;
+        test.covered_files = drain_covered_traces(&mut self.covered_traces);
+        self.tests.push(test);
+    }
+}
+
+impl FrameSink for CoverageBuilder {
+    fn push(&mut self, frame: &DefmtFrame) -> Result<(), SinkError> {
+        self.add_frame(frame);
+        Ok(())
+    }
+}
+
+/// Returns the log lines of every test, using the test name as key.
+///
+/// Logs are captured between the "running" entry of a test and the next test-fn entry or "all tests passed!".
+/// Requirement coverage logs are omitted.
+pub fn test_logs(frames: &[DefmtFrame]) -> HashMap<String, Vec<String>> {
+    let test_fn_matcher = test_fn_matcher();
+    let mut logs: HashMap<String, Vec<String>> = HashMap::new();
+    let mut current_test: Option<String> = None;
+
+    for frame in frames {
+        if let Some(captured_test_fn) = test_fn_matcher.captures(&frame.data) {
+            current_test = match (
+                captured_test_fn.name("state").map(|m| m.as_str()),
+                captured_test_fn.name("fn_name"),
+            ) {
+                (Some("running"), Some(fn_name)) => test_fn_name(frame, fn_name.as_str()).ok(),
+                _ => None,
+            };
+
+            if let Some(test_name) = &current_test {
+                logs.entry(test_name.clone()).or_default();
+            }
+        } else if frame.data == "all tests passed!" {
+            current_test = None;
+        } else if let Some(test_name) = &current_test {
+            if mantra_rust_macros::extract::extract_first_coverage(&frame.data).is_none() {
+                let line = match frame.level {
+                    Some(level) => format!("{level} {}", frame.data),
+                    None => frame.data.clone(),
+                };
+                logs.entry(test_name.clone()).or_default().push(line);
+            }
+        }
+    }
+
+    logs
+}
+
+/// Information about why a test failed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TestFailure {
+    /// Short failure reason like the panic message, `timeout`, or `HardFault`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Panic and error messages logged while the test was running.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<String>,
+}
+
+/// Returns the defmt frames stored in the logs of the given test run.
+pub fn logged_frames(test_run: &TestRun) -> Vec<DefmtFrame> {
+    test_run
+        .logs
+        .as_deref()
+        .and_then(|logs| serde_json::from_str(logs).ok())
+        .unwrap_or_default()
+}
+
+/// Returns the names of all tests that failed in the given coverage.
+pub fn failed_tests(coverage: &CoverageSchema) -> Vec<String> {
+    coverage
+        .test_runs
+        .iter()
+        .flat_map(|test_run| test_run.tests.iter())
+        .filter(|test| test.state == TestState::Failed)
+        .map(|test| test.name.clone())
+        .collect()
 }
 
 fn drain_covered_traces(
@@ -184,4 +332,124 @@ fn drain_covered_traces(
     covered_files
 }
 
+/// Creates the full test name consisting of the module path of the test-fn entry and the name of the test-fn.
+fn test_fn_name(frame: &DefmtFrame, fn_name: &str) -> Result<String, CoverageError> {
+    let Some(mod_path) = &frame.location.module_path else {
+        return Err(CoverageError::Match(format!(
+            "Missing line location information for log entry '{}'.",
+            frame.data
+        )));
+    };
+    let mod_path_str = format!(
+        "{}{}",
+        mod_path.crate_name,
+        if mod_path.modules.is_empty() {
+            String::new()
+        } else {
+            format!("::{}", mod_path.modules.join("::"))
+        }
+    );
+
+    Ok(format!("{}::{}", mod_path_str, fn_name))
+}
+
+pub(crate) fn test_fn_matcher() -> &'static Regex {
+    TEST_FN_MATCHER.get_or_init(|| {
+        Regex::new(
+            r"^\(\d+/(?<nr_tests>\d+)\)\s(?<state>(?:running)|(?:ignoring))\s`(?<fn_name>.+)`...",
+        )
+        .expect("Could not create regex matcher for defmt test-fn entries.")
+    })
+}
+
 static TEST_FN_MATCHER: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+
+/// Prefix of defmt frames logged by panic handlers like `panic-probe`.
+pub(crate) const PANIC_PREFIX: &str = "panicked at";
+
+#[cfg(test)]
+mod test {
+    use defmt_json_schema::v1::{JsonFrame, Location, ModulePath};
+    use mantra_schema::coverage::TestState;
+
+    use super::{failed_tests, logged_frames, test_logs, CoverageBuilder};
+
+    fn frame(data: &str) -> JsonFrame {
+        JsonFrame {
+            data: data.to_string(),
+            host_timestamp: 0,
+            level: Some(log::Level::Info),
+            location: Location {
+                file: Some("tests/emb.rs".to_string()),
+                line: Some(1),
+                module_path: Some(ModulePath {
+                    crate_name: "emb".to_string(),
+                    modules: vec!["tests".to_string()],
+                    function: "__defmt_test_entry".to_string(),
+                }),
+            },
+            target_timestamp: String::new(),
+        }
+    }
+
+    fn builder(frames: &[JsonFrame]) -> CoverageBuilder {
+        let mut builder = CoverageBuilder::default();
+        for frame in frames {
+            builder.add_frame(frame);
+        }
+        builder
+    }
+
+    #[test]
+    fn unfinished_test_is_failed() {
+        let frames = vec![
+            frame("(1/2) running `passing`..."),
+            frame("(2/2) running `panicking`..."),
+            frame("panicked at 'explicit panic'"),
+        ];
+
+        let builder = builder(&frames);
+        assert_eq!(builder.unfinished_test(), Some("emb::tests::panicking"));
+
+        let coverage = builder.build("run".to_string(), None).unwrap();
+        let tests = &coverage.test_runs[0].tests;
+
+        assert_eq!(tests.len(), 2, "Unfinished test not added to the test run.");
+        assert_eq!(tests[0].state, TestState::Passed);
+        assert_eq!(tests[1].state, TestState::Failed);
+        assert_eq!(failed_tests(&coverage), vec!["emb::tests::panicking"]);
+
+        let logs = test_logs(&logged_frames(&coverage.test_runs[0]));
+        assert_eq!(logs.len(), 1, "Logs of passed tests are kept.");
+        assert_eq!(
+            logs["emb::tests::panicking"],
+            vec!["INFO panicked at 'explicit panic'"]
+        );
+    }
+
+    #[test]
+    fn panic_is_failure_reason() {
+        let mut error = frame("value out of range");
+        error.level = Some(log::Level::Error);
+        let frames = vec![
+            frame("(1/2) running `passing`..."),
+            frame("(2/2) running `panicking`..."),
+            error,
+            frame("panicked at 'explicit panic'"),
+        ];
+
+        let builder = builder(&frames);
+        let failures = builder.failures();
+        let failure = &failures["emb::tests::panicking"];
+
+        assert_eq!(failures.len(), 1, "Passing test has a failure.");
+        assert_eq!(
+            failure.reason.as_deref(),
+            Some("panicked at 'explicit panic'")
+        );
+        assert_eq!(
+            failure.messages,
+            vec!["value out of range", "panicked at 'explicit panic'"]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{
+    cfg::{DecodeCmdConfig, ResolvedConfig},
+    defmt::{self, DefmtLogs, EndConditions, ReadEnd},
+    output::{self, OutputSettings, RunStatus},
+    sink::RunSinks,
+    RunnerError,
+};
+
+/// Decodes recorded RTT data the same way as during a run, and stores logs and test results.
+pub async fn run(main_cfg: &ResolvedConfig, cfg: DecodeCmdConfig) -> Result<(), RunnerError> {
+    let output_dir = match cfg.output_dir {
+        Some(dir) => dir,
+        None => cfg
+            .rtt_data
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default(),
+    };
+
+    output::create_output_dir(&output_dir).await?;
+
+    let end_conditions = EndConditions {
+        test_timeout: None,
+        completion_pattern: main_cfg
+            .runner_cfg
+            .completion_pattern
+            .as_ref()
+            .map(|pattern| regex::Regex::new(pattern))
+            .transpose()
+            .map_err(|err| RunnerError::Config(crate::cfg::ConfigError::CompletionPattern(err)))?,
+    };
+
+    let (sinks, end) = defmt::decode_rtt_file(
+        &cfg.elf,
+        &main_cfg.workspace_dir,
+        &cfg.rtt_data,
+        &end_conditions,
+        RunSinks::create(&output_dir)
+            .await
+            .map_err(|err| RunnerError::Defmt(err.to_string()))?,
+    )
+    .await;
+    // frames decoded before an error are stored anyway
+    let (end, decode_result) = match end {
+        Ok(end) => (end, Ok(())),
+        Err(err) => (
+            ReadEnd::Failed,
+            Err(RunnerError::Defmt(format!(
+                "Failed decoding RTT data. Cause: {err}"
+            ))),
+        ),
+    };
+
+    let output_settings = OutputSettings {
+        output_dir,
+        rel_binary: output::relative_binary_path(&cfg.elf),
+        run_name: cfg.run_name,
+        data_filepath: cfg.data_filepath,
+        junit: cfg.junit.unwrap_or(main_cfg.runner_cfg.junit),
+        register: true,
+    };
+    let failed_tests = output::store_results(
+        main_cfg,
+        &output_settings,
+        DefmtLogs { sinks, end },
+        RunStatus::default(),
+    )
+    .await?;
+
+    decode_result?;
+
+    if !failed_tests.is_empty() {
+        return Err(RunnerError::TestsFailed(failed_tests));
+    }
+
+    Ok(())
+}
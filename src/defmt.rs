@@ -1,12 +1,16 @@
-use std::{
-    io::Read,
-    path::Path,
-    sync::{atomic::AtomicBool, Arc},
-    time::Duration,
-};
+use std::{path::Path, time::Duration};
 
 use defmt_decoder::{DecodeError, Frame, Locations, Table};
 use defmt_json_schema::v1::{JsonFrame, Location as JsonLocation, ModulePath};
+use regex::Regex;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::sink::{FrameSink, RunSinks, SinkError};
 
 #[derive(Debug, thiserror::Error)]
 pub enum DefmtError {
@@ -22,20 +26,291 @@ pub enum DefmtError {
     ReadBinary(std::io::Error),
     #[error("Missing defmt data in given binary.")]
     MissingDefmt,
+    #[error("Failed recording raw RTT data. Cause: {}", .0)]
+    RawLog(std::io::Error),
+    #[error("Failed reading recorded RTT data. Cause: {}", .0)]
+    ReadRttData(std::io::Error),
+    #[error("Failed handling a frame. Cause: {}", .0)]
+    Sink(#[from] SinkError),
+}
+
+/// Waits until the given deadline, or forever if no deadline is set.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sinks that received the frames read from the target, and the reason why reading ended.
+#[derive(Debug)]
+pub struct DefmtLogs {
+    pub sinks: RunSinks,
+    pub end: ReadEnd,
+}
+
+/// Reasons to stop reading defmt frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadEnd {
+    /// Reading was stopped by the end signal.
+    EndSignal,
+    /// The target closed the connection.
+    ConnectionClosed,
+    /// A test did not finish within the test timeout.
+    TestTimeout,
+    /// The target signaled that the run is complete.
+    Completed(CompletionMarker),
+    /// Reading or decoding failed.
+    Failed,
+}
+
+/// Frames signaling that the run on the target is complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionMarker {
+    /// `defmt-test` finished all tests.
+    AllTestsPassed,
+    /// The target panicked.
+    Panic,
+    /// The frame matched the custom completion pattern.
+    Pattern,
+}
+
+impl std::fmt::Display for CompletionMarker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompletionMarker::AllTestsPassed => write!(f, "all tests passed"),
+            CompletionMarker::Panic => write!(f, "panic"),
+            CompletionMarker::Pattern => write!(f, "completion pattern"),
+        }
+    }
+}
+
+/// Conditions to stop reading defmt frames besides the end signal.
+#[derive(Debug, Clone, Default)]
+pub struct EndConditions {
+    /// Maximum duration of one defmt test.
+    pub test_timeout: Option<Duration>,
+    /// Custom pattern for frames that signal the end of the run.
+    pub completion_pattern: Option<Regex>,
+}
+
+impl EndConditions {
+    /// Returns the completion marker the given frame represents, if any.
+    pub fn completion_marker(&self, frame: &JsonFrame) -> Option<CompletionMarker> {
+        if frame.data == "all tests passed!" {
+            Some(CompletionMarker::AllTestsPassed)
+        } else if frame.data.starts_with(crate::coverage::PANIC_PREFIX) {
+            Some(CompletionMarker::Panic)
+        } else if self
+            .completion_pattern
+            .as_ref()
+            .is_some_and(|pattern| pattern.is_match(&frame.data))
+        {
+            Some(CompletionMarker::Pattern)
+        } else {
+            None
+        }
+    }
+}
+
+/// Duration without new data after which reading ends once the end signal is set.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Decodes the RTT data recorded in the given file, and pushes all frames to the given sink.
+///
+/// The sink is returned together with the result, so frames received before an error are not lost.
+pub async fn decode_rtt_file<S: FrameSink + 'static>(
+    binary: &Path,
+    workspace_root: &Path,
+    rtt_data: &Path,
+    end_conditions: &EndConditions,
+    sink: S,
+) -> (S, Result<ReadEnd, DefmtError>) {
+    let file = match tokio::fs::File::open(rtt_data).await {
+        Ok(file) => file,
+        Err(err) => return (sink, Err(DefmtError::ReadRttData(err))),
+    };
+
+    read_defmt_frames(
+        binary,
+        workspace_root,
+        file,
+        CancellationToken::new(),
+        end_conditions,
+        None,
+        sink,
+    )
+    .await
+}
+
+/// Reads defmt frames from the given RTT source, and pushes every frame to the given sink.
+///
+/// Frames are decoded on a blocking thread, because the defmt stream decoder cannot be moved between threads.
+/// Once `end_signal` is cancelled, data still in transit is read until no data arrived for a short duration.
+/// If `raw_log` is set, all received bytes are written to this file before decoding.
+///
+/// The sink is finished once reading ended, and returned together with the result, even if reading failed.
+pub async fn read_defmt_frames<S: FrameSink + 'static>(
+    binary: &Path,
+    workspace_root: &Path,
+    stream: impl AsyncRead + Unpin,
+    end_signal: CancellationToken,
+    end_conditions: &EndConditions,
+    raw_log: Option<&Path>,
+    mut sink: S,
+) -> (S, Result<ReadEnd, DefmtError>) {
+    let mut raw_log = match raw_log {
+        Some(path) => match tokio::fs::File::create(path).await {
+            Ok(file) => Some(file),
+            Err(err) => return (sink, Err(DefmtError::RawLog(err))),
+        },
+        None => None,
+    };
+
+    let (bytes_tx, bytes_rx) = mpsc::channel(BYTES_CHANNEL_SIZE);
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+    let binary = binary.to_path_buf();
+    let workspace_root = workspace_root.to_path_buf();
+    let decode_conditions = end_conditions.clone();
+
+    let decoder = tokio::task::spawn_blocking(move || {
+        let decoded = decode_frames(
+            &binary,
+            &workspace_root,
+            bytes_rx,
+            events_tx,
+            &decode_conditions,
+            &mut sink,
+        );
+        let finished = sink.finish().map_err(DefmtError::from);
+        (sink, decoded.and(finished))
+    });
+
+    let end = read_bytes(
+        stream,
+        end_signal,
+        end_conditions.test_timeout,
+        &mut raw_log,
+        bytes_tx,
+        events_rx,
+    )
+    .await;
+    let flushed = match &mut raw_log {
+        Some(raw_log) => raw_log.flush().await.map_err(DefmtError::RawLog),
+        None => Ok(()),
+    };
+
+    // remaining bytes are decoded before the decoder ends, because the bytes channel is closed by `read_bytes`
+    let (sink, decoded) = decoder.await.expect("Decoding defmt frames panicked.");
+
+    // decoding errors take precedence, because reading ends once the decoder stopped
+    let result = decoded.and(end).and_then(|end| flushed.map(|_| end));
+    (sink, result)
 }
 
-pub fn read_defmt_frames(
+/// Events the decoder sends to the reader to evaluate the end conditions.
+#[derive(Debug)]
+enum DecodeEvent {
+    TestStarted,
+    TestEnded,
+    Completed(CompletionMarker),
+}
+
+/// Maximum number of received chunks that wait for decoding.
+const BYTES_CHANNEL_SIZE: usize = 64;
+
+/// Reads bytes from the given RTT source, and forwards them to the decoder until one of the end conditions is met.
+async fn read_bytes(
+    mut stream: impl AsyncRead + Unpin,
+    end_signal: CancellationToken,
+    test_timeout: Option<Duration>,
+    raw_log: &mut Option<tokio::fs::File>,
+    bytes: mpsc::Sender<Vec<u8>>,
+    mut events: mpsc::UnboundedReceiver<DecodeEvent>,
+) -> Result<ReadEnd, DefmtError> {
+    const READ_BUFFER_SIZE: usize = 1024;
+    let mut buf = [0; READ_BUFFER_SIZE];
+
+    // start of the test that is currently running
+    let mut test_start: Option<Instant> = None;
+    // frames still in transit are read before ending on the end signal
+    let mut drain_deadline: Option<Instant> = None;
+
+    loop {
+        let test_deadline = test_start
+            .zip(test_timeout)
+            .map(|(start, timeout)| start + timeout);
+
+        tokio::select! {
+            read = stream.read(&mut buf) => {
+                let n = match read {
+                    Ok(0) => return Ok(ReadEnd::ConnectionClosed),
+                    Ok(len) => len,
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::ConnectionReset
+                        ) =>
+                    {
+                        return Ok(ReadEnd::ConnectionClosed);
+                    }
+                    Err(err) => return Err(DefmtError::TcpError(err.to_string())),
+                };
+
+                if let Some(raw_log) = raw_log {
+                    raw_log
+                        .write_all(&buf[..n])
+                        .await
+                        .map_err(DefmtError::RawLog)?;
+                }
+
+                if bytes.send(buf[..n].to_vec()).await.is_err() {
+                    // decoder stopped => only remaining events are relevant
+                    while let Some(event) = events.recv().await {
+                        if let DecodeEvent::Completed(marker) = event {
+                            return Ok(ReadEnd::Completed(marker));
+                        }
+                    }
+                    return Ok(ReadEnd::ConnectionClosed);
+                }
+
+                if let Some(deadline) = &mut drain_deadline {
+                    *deadline = Instant::now() + DRAIN_TIMEOUT;
+                }
+            }
+            event = events.recv() => match event {
+                Some(DecodeEvent::TestStarted) => test_start = Some(Instant::now()),
+                Some(DecodeEvent::TestEnded) => test_start = None,
+                Some(DecodeEvent::Completed(marker)) => return Ok(ReadEnd::Completed(marker)),
+                // decoder failed => its error is returned instead
+                None => return Ok(ReadEnd::ConnectionClosed),
+            },
+            _ = end_signal.cancelled(), if drain_deadline.is_none() => {
+                drain_deadline = Some(Instant::now() + DRAIN_TIMEOUT);
+            }
+            _ = sleep_until(drain_deadline), if drain_deadline.is_some() => return Ok(ReadEnd::EndSignal),
+            _ = sleep_until(test_deadline), if drain_deadline.is_none() => return Ok(ReadEnd::TestTimeout),
+        }
+    }
+}
+
+/// Decodes received bytes, and pushes the decoded frames to the given sink.
+///
+/// Decoding ends once the bytes channel is closed, or a frame signals completion.
+fn decode_frames(
     binary: &Path,
     workspace_root: &Path,
-    mut stream: std::net::TcpStream,
-    end_signal: Arc<AtomicBool>,
-) -> Result<Vec<JsonFrame>, DefmtError> {
-    let bytes = std::fs::read(binary).map_err(DefmtError::ReadBinary)?;
-    let table = Table::parse(&bytes)
+    mut bytes: mpsc::Receiver<Vec<u8>>,
+    events: mpsc::UnboundedSender<DecodeEvent>,
+    end_conditions: &EndConditions,
+    sink: &mut impl FrameSink,
+) -> Result<(), DefmtError> {
+    let elf = std::fs::read(binary).map_err(DefmtError::ReadBinary)?;
+    let table = Table::parse(&elf)
         .map_err(|_| DefmtError::MissingDefmt)?
         .ok_or(DefmtError::MissingDefmt)?;
     let locs = table
-        .get_locations(&bytes)
+        .get_locations(&elf)
         .map_err(|_| DefmtError::MissingDefmt)?;
 
     // check if the locations info contains all the indicies
@@ -46,43 +321,11 @@ pub fn read_defmt_frames(
         None
     };
 
-    const READ_BUFFER_SIZE: usize = 1024;
-    let mut buf = [0; READ_BUFFER_SIZE];
     let mut decoder = table.new_stream_decoder();
     let mut stream_decoder = Box::pin(&mut decoder);
 
-    let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
-    let mut json_frames = Vec::new();
-
-    loop {
-        // read from tcpstream and push it to the decoder
-        if end_signal.load(std::sync::atomic::Ordering::Relaxed) {
-            return Ok(json_frames);
-        }
-
-        let n = match stream.read(&mut buf) {
-            Ok(len) => {
-                if len == 0 {
-                    continue;
-                } else {
-                    len
-                }
-            }
-            Err(err) => {
-                if err.kind() == std::io::ErrorKind::TimedOut {
-                    continue;
-                } else if matches!(
-                    err.kind(),
-                    std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::ConnectionReset
-                ) {
-                    return Ok(json_frames);
-                } else {
-                    return Err(DefmtError::TcpError(err.to_string()));
-                }
-            }
-        };
-
-        stream_decoder.received(&buf[..n]);
+    while let Some(data) = bytes.blocking_recv() {
+        stream_decoder.received(&data);
 
         // decode the received data
         loop {
@@ -90,67 +333,27 @@ pub fn read_defmt_frames(
                 Ok(frame) => {
                     let json_frame = create_json_frame(workspace_root, &frame, &locs);
 
-                    let mod_path = if let Some(mod_path) = &json_frame.location.module_path {
-                        if mod_path.modules.is_empty() {
-                            Some(format!("{}::{}", mod_path.crate_name, mod_path.function))
-                        } else {
-                            Some(format!(
-                                "{}::{}::{}",
-                                mod_path.crate_name,
-                                mod_path.modules.join("::"),
-                                mod_path.function
-                            ))
-                        }
-                    } else {
-                        None
-                    };
-
-                    // use kv feature due to lifetime problems with arg
-                    let val = Some([("msg", log::kv::Value::from_display(&json_frame.data))]);
-
-                    match json_frame.level {
-                        Some(level) => {
-                            let log_record = log::RecordBuilder::new()
-                                .level(level)
-                                .file(json_frame.location.file.as_deref())
-                                .line(json_frame.location.line)
-                                .module_path(mod_path.as_deref())
-                                .target("embedded")
-                                .key_values(&val)
-                                .build();
-                            log::logger().log(&log_record);
-                        }
-                        None => {
-                            // mantra coverage logs not printed to remove clutter
-                            if mantra_rust_macros::extract::extract_first_coverage(&json_frame.data)
-                                .is_none()
-                            {
-                                println!("TARGET-PRINT | {}", json_frame.data);
-
-                                if log::Level::Trace <= log::STATIC_MAX_LEVEL
-                                    && log::Level::Trace <= log::max_level()
-                                {
-                                    let location = if json_frame.location.file.is_some()
-                                        && json_frame.location.line.is_some()
-                                        && mod_path.is_some()
-                                    {
-                                        format!(
-                                            "{} in {}:{}",
-                                            mod_path.unwrap(),
-                                            json_frame.location.file.as_ref().unwrap(),
-                                            json_frame.location.line.unwrap(),
-                                        )
-                                    } else {
-                                        "no-location info available".to_string()
-                                    };
-
-                                    println!("             | => {location}");
-                                }
-                            }
-                        }
+                    // reader might already have ended => events are irrelevant
+                    if let Some(test_fn) =
+                        crate::coverage::test_fn_matcher().captures(&json_frame.data)
+                    {
+                        let _ = events.send(
+                            if test_fn.name("state").map(|m| m.as_str()) == Some("running") {
+                                DecodeEvent::TestStarted
+                            } else {
+                                DecodeEvent::TestEnded
+                            },
+                        );
+                    } else if json_frame.data == "all tests passed!" {
+                        let _ = events.send(DecodeEvent::TestEnded);
                     }
 
-                    json_frames.push(json_frame);
+                    sink.push(&json_frame)?;
+
+                    if let Some(marker) = end_conditions.completion_marker(&json_frame) {
+                        let _ = events.send(DecodeEvent::Completed(marker));
+                        return Ok(());
+                    }
                 }
                 Err(DecodeError::UnexpectedEof) => break,
                 Err(DecodeError::Malformed) => match table.encoding().can_recover() {
@@ -165,6 +368,8 @@ pub fn read_defmt_frames(
             }
         }
     }
+
+    Ok(())
 }
 
 pub type LocationInfo = (Option<String>, Option<u32>, Option<String>);
@@ -0,0 +1,496 @@
+//! Client to control GDB over the GDB/MI protocol.
+//!
+//! see: https://sourceware.org/gdb/current/onlinedocs/gdb.html/GDB_002fMI.html
+
+use std::{collections::VecDeque, path::Path, process::Stdio, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin},
+    sync::mpsc,
+};
+
+use crate::process::Registration;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MiError {
+    #[error("Could not start GDB. Cause: {}", .0)]
+    Spawn(std::io::Error),
+    #[error("Could not send command to GDB. Cause: {}", .0)]
+    Send(std::io::Error),
+    #[error("{}", .0)]
+    Command(String),
+    #[error("GDB exited unexpectedly.")]
+    Exited,
+}
+
+/// Value of a result in a GDB/MI record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiValue {
+    Const(String),
+    Tuple(Vec<(String, MiValue)>),
+    List(Vec<MiValue>),
+}
+
+impl MiValue {
+    /// Returns the content of a const value.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MiValue::Const(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// One line of GDB/MI output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiRecord {
+    /// Result of a command (`^done`, `^running`, `^connected`, `^error`, `^exit`).
+    Result {
+        token: Option<u32>,
+        class: String,
+        results: Vec<(String, MiValue)>,
+    },
+    /// Change of the execution state of the target (`*running`, `*stopped`).
+    Exec {
+        class: String,
+        results: Vec<(String, MiValue)>,
+    },
+    /// Supplementary information (`=...`, `+...`).
+    Notify,
+    /// Output of console commands (`~"..."`).
+    Console(String),
+    /// Output of the target or the remote server (`@"..."`).
+    Target(String),
+    /// Internal messages of GDB (`&"..."`).
+    Log(String),
+    /// The `(gdb)` prompt.
+    Prompt,
+}
+
+/// Reasons why the target stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// The breakpoint with the given number was hit.
+    BreakpointHit(String),
+    SignalReceived(String),
+    /// The program exited with the given exit code.
+    Exited(i64),
+    /// The program was terminated by the given signal.
+    ExitedSignalled(String),
+    Other(String),
+}
+
+/// Output of a successful command.
+#[derive(Debug, Clone, Default)]
+pub struct MiOutput {
+    pub class: String,
+    pub results: Vec<(String, MiValue)>,
+    /// Console output that was received while the command was executed.
+    pub console: String,
+}
+
+/// GDB process controlled over GDB/MI.
+pub struct GdbMi {
+    child: Child,
+    _registration: Registration,
+    stdin: ChildStdin,
+    records: mpsc::UnboundedReceiver<MiRecord>,
+    stops: VecDeque<StopReason>,
+    next_token: u32,
+}
+
+impl GdbMi {
+    /// Starts GDB for the given binary with the GDB/MI interpreter.
+    ///
+    /// The given arguments are passed before the binary.
+    pub fn spawn(
+        gdb: &str,
+        args: &[String],
+        binary: &Path,
+        workspace_dir: &Path,
+    ) -> Result<Self, MiError> {
+        let mut cmd = tokio::process::Command::new(gdb);
+        cmd.args(["--interpreter=mi3", "-q"])
+            .args(args)
+            .arg(binary)
+            .current_dir(workspace_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+
+        let (mut child, registration) = crate::process::spawn(&mut cmd).map_err(MiError::Spawn)?;
+
+        let stdin = child.stdin.take().expect("GDB stdin is piped.");
+        let stdout = child.stdout.take().expect("GDB stdout is piped.");
+        let (sender, records) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                match parse_record(&line) {
+                    Some(record) => {
+                        if let MiRecord::Console(output)
+                        | MiRecord::Target(output)
+                        | MiRecord::Log(output) = &record
+                        {
+                            log::debug!(target: "gdb", "{}", output.trim_end());
+                        }
+
+                        if sender.send(record).is_err() {
+                            break;
+                        }
+                    }
+                    None => log::debug!(target: "gdb", "{line}"),
+                }
+            }
+        });
+
+        Ok(GdbMi {
+            child,
+            _registration: registration,
+            stdin,
+            records,
+            stops: VecDeque::new(),
+            next_token: 1,
+        })
+    }
+
+    /// Executes a GDB/MI command, and waits for its result.
+    pub async fn execute(&mut self, command: &str) -> Result<MiOutput, MiError> {
+        let token = self.next_token;
+        self.next_token += 1;
+
+        self.stdin
+            .write_all(format!("{token}{command}\n").as_bytes())
+            .await
+            .map_err(MiError::Send)?;
+        self.stdin.flush().await.map_err(MiError::Send)?;
+
+        let mut console = String::new();
+
+        loop {
+            match self.records.recv().await.ok_or(MiError::Exited)? {
+                MiRecord::Result {
+                    token: Some(result_token),
+                    class,
+                    results,
+                } if result_token == token => {
+                    if class == "error" {
+                        let msg = find_const(&results, "msg").unwrap_or_default();
+                        return Err(MiError::Command(msg.to_string()));
+                    }
+
+                    return Ok(MiOutput {
+                        class,
+                        results,
+                        console,
+                    });
+                }
+                MiRecord::Console(output) | MiRecord::Target(output) => console.push_str(&output),
+                MiRecord::Exec { class, results } if class == "stopped" => {
+                    self.stops.push_back(stop_reason(&results));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Executes a CLI command (e.g. `monitor rtt start`) using the console interpreter.
+    pub async fn console(&mut self, command: &str) -> Result<MiOutput, MiError> {
+        self.execute(&format!(
+            "-interpreter-exec console {}",
+            quote(command.trim())
+        ))
+        .await
+    }
+
+    /// Discards stops received while executing commands (e.g. while connecting to the target).
+    pub fn clear_stops(&mut self) {
+        self.stops.clear();
+    }
+
+    /// Waits until the target stops.
+    pub async fn wait_for_stop(&mut self) -> Result<StopReason, MiError> {
+        if let Some(reason) = self.stops.pop_front() {
+            return Ok(reason);
+        }
+
+        loop {
+            match self.records.recv().await.ok_or(MiError::Exited)? {
+                MiRecord::Exec { class, results } if class == "stopped" => {
+                    return Ok(stop_reason(&results));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Lets GDB exit, and kills it if it does not exit within the given timeout.
+    pub async fn exit(mut self, timeout: Duration) -> std::io::Result<std::process::ExitStatus> {
+        // GDB might exit before sending the result
+        let _ = tokio::time::timeout(timeout, self.execute("-gdb-exit")).await;
+
+        match tokio::time::timeout(timeout, self.child.wait()).await {
+            Ok(status) => status,
+            Err(_) => self.kill().await,
+        }
+    }
+
+    /// Inserts a breakpoint at the given location, and returns the number of the breakpoint.
+    pub async fn insert_breakpoint(&mut self, location: &str) -> Result<String, MiError> {
+        let output = self.execute(&format!("-break-insert {location}")).await?;
+
+        output
+            .results
+            .iter()
+            .find_map(|(key, value)| match value {
+                MiValue::Tuple(bkpt) if key == "bkpt" => find_const(bkpt, "number"),
+                _ => None,
+            })
+            .map(str::to_string)
+            .ok_or(MiError::Command(format!(
+                "No breakpoint number returned for '{location}'."
+            )))
+    }
+
+    /// Kills GDB.
+    pub async fn kill(mut self) -> std::io::Result<std::process::ExitStatus> {
+        self.child.kill().await?;
+        self.child.wait().await
+    }
+}
+
+fn stop_reason(results: &[(String, MiValue)]) -> StopReason {
+    match find_const(results, "reason") {
+        Some("breakpoint-hit") => StopReason::BreakpointHit(
+            find_const(results, "bkptno")
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        Some("signal-received") => StopReason::SignalReceived(
+            find_const(results, "signal-name")
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        Some("exited-normally") => StopReason::Exited(0),
+        // exit code is given in octal
+        Some("exited") => StopReason::Exited(
+            find_const(results, "exit-code")
+                .and_then(|code| i64::from_str_radix(code, 8).ok())
+                .unwrap_or_default(),
+        ),
+        Some("exited-signalled") => StopReason::ExitedSignalled(
+            find_const(results, "signal-name")
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        reason => StopReason::Other(reason.unwrap_or_default().to_string()),
+    }
+}
+
+fn find_const<'a>(results: &'a [(String, MiValue)], key: &str) -> Option<&'a str> {
+    results
+        .iter()
+        .find(|(k, _)| k == key)
+        .and_then(|(_, value)| value.as_str())
+}
+
+/// Quotes the given string as C-string.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Parses one line of GDB/MI output.
+///
+/// Returns `None` if the line is no GDB/MI record (e.g. output of programs started by GDB).
+pub fn parse_record(line: &str) -> Option<MiRecord> {
+    let line = line.trim_end();
+
+    if line == "(gdb)" {
+        return Some(MiRecord::Prompt);
+    }
+
+    let token_end = line
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(line.len());
+    let token = line[..token_end].parse().ok();
+    let rest = &line[token_end..];
+    let mut chars = rest.chars();
+    let kind = chars.next()?;
+    let content = chars.as_str();
+
+    match kind {
+        '~' => parse_cstring(content).map(|(s, _)| MiRecord::Console(s)),
+        '@' => parse_cstring(content).map(|(s, _)| MiRecord::Target(s)),
+        '&' => parse_cstring(content).map(|(s, _)| MiRecord::Log(s)),
+        '^' | '*' => {
+            let (class, results) = content.split_once(',').unwrap_or((content, ""));
+            let results = parse_results(results)?;
+
+            if kind == '^' {
+                Some(MiRecord::Result {
+                    token,
+                    class: class.to_string(),
+                    results,
+                })
+            } else {
+                Some(MiRecord::Exec {
+                    class: class.to_string(),
+                    results,
+                })
+            }
+        }
+        '=' | '+' => Some(MiRecord::Notify),
+        _ => None,
+    }
+}
+
+fn parse_results(mut s: &str) -> Option<Vec<(String, MiValue)>> {
+    let mut results = Vec::new();
+
+    while !s.is_empty() {
+        let (result, rest) = parse_result(s)?;
+        results.push(result);
+        s = rest.strip_prefix(',').unwrap_or(rest);
+    }
+
+    Some(results)
+}
+
+fn parse_result(s: &str) -> Option<((String, MiValue), &str)> {
+    let (key, rest) = s.split_once('=')?;
+    let (value, rest) = parse_value(rest)?;
+    Some(((key.to_string(), value), rest))
+}
+
+fn parse_value(s: &str) -> Option<(MiValue, &str)> {
+    match s.chars().next()? {
+        '"' => parse_cstring(s).map(|(value, rest)| (MiValue::Const(value), rest)),
+        '{' => {
+            let mut rest = &s[1..];
+            let mut tuple = Vec::new();
+
+            while !rest.starts_with('}') {
+                let (result, r) = parse_result(rest)?;
+                tuple.push(result);
+                rest = r.strip_prefix(',').unwrap_or(r);
+            }
+
+            Some((MiValue::Tuple(tuple), &rest[1..]))
+        }
+        '[' => {
+            let mut rest = &s[1..];
+            let mut list = Vec::new();
+
+            while !rest.starts_with(']') {
+                // lists contain either values or results
+                let (value, r) = match parse_value(rest) {
+                    Some(parsed) => parsed,
+                    None => {
+                        let (result, r) = parse_result(rest)?;
+                        (MiValue::Tuple(vec![result]), r)
+                    }
+                };
+                list.push(value);
+                rest = r.strip_prefix(',').unwrap_or(r);
+            }
+
+            Some((MiValue::List(list), &rest[1..]))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a C-string at the start of the given string, and returns the unescaped content and the remaining string.
+fn parse_cstring(s: &str) -> Option<(String, &str)> {
+    let mut chars = s.char_indices();
+    if chars.next()?.1 != '"' {
+        return None;
+    }
+
+    let mut content = String::new();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((content, &s[i + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => content.push('\n'),
+                't' => content.push('\t'),
+                'r' => content.push('\r'),
+                escaped => content.push(escaped),
+            },
+            _ => content.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_record, stop_reason, MiRecord, MiValue, StopReason};
+
+    #[test]
+    fn stopped_record() {
+        let record = parse_record(
+            r#"*stopped,reason="breakpoint-hit",disp="keep",bkptno="1",frame={addr="0x08000400",func="main",args=[]},thread-id="1",stopped-threads="all""#,
+        )
+        .unwrap();
+
+        let MiRecord::Exec { class, results } = record else {
+            panic!("Stopped record not parsed as exec record.");
+        };
+
+        assert_eq!(class, "stopped");
+        assert_eq!(
+            stop_reason(&results),
+            StopReason::BreakpointHit("1".to_string())
+        );
+        assert!(
+            matches!(&results[3].1, MiValue::Tuple(frame) if frame[1].1 == MiValue::Const("main".to_string())),
+            "Frame tuple not parsed."
+        );
+    }
+
+    #[test]
+    fn error_record_with_token() {
+        let record = parse_record(r#"3^error,msg="Load failed: \"no device\"""#).unwrap();
+
+        assert_eq!(
+            record,
+            MiRecord::Result {
+                token: Some(3),
+                class: "error".to_string(),
+                results: vec![(
+                    "msg".to_string(),
+                    MiValue::Const("Load failed: \"no device\"".to_string())
+                )],
+            }
+        );
+    }
+
+    #[test]
+    fn exit_code_is_octal() {
+        let MiRecord::Exec { results, .. } =
+            parse_record(r#"*stopped,reason="exited",exit-code="010""#).unwrap()
+        else {
+            panic!("Stopped record not parsed as exec record.");
+        };
+
+        assert_eq!(stop_reason(&results), StopReason::Exited(8));
+    }
+}
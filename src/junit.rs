@@ -0,0 +1,212 @@
+use std::fmt::Write;
+
+use mantra_schema::coverage::{CoverageSchema, Test, TestRun, TestState};
+use time::format_description::well_known::Iso8601;
+
+use crate::coverage;
+
+/// Creates a JUnit XML report for all test runs of the given coverage.
+///
+/// Logs of failed tests are taken from the logs of the test run.
+pub fn junit_from_coverage(coverage: &CoverageSchema) -> String {
+    let runs = &coverage.test_runs;
+    let nr_tests: usize = runs.iter().map(|run| run.tests.len()).sum();
+    let nr_failures: usize = runs.iter().map(|run| count(run, is_failed)).sum();
+    let nr_skipped: usize = runs.iter().map(|run| count(run, is_skipped)).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"embedded-runner\" tests=\"{nr_tests}\" failures=\"{nr_failures}\" skipped=\"{nr_skipped}\">"
+    );
+
+    for test_run in runs {
+        write_testsuite(&mut xml, test_run);
+    }
+
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn write_testsuite(xml: &mut String, test_run: &TestRun) {
+    let test_logs = coverage::test_logs(&coverage::logged_frames(test_run));
+    let timestamp = test_run.date.format(&Iso8601::DEFAULT).unwrap_or_default();
+
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" timestamp=\"{}\">",
+        escape(&test_run.name),
+        test_run.tests.len(),
+        count(test_run, is_failed),
+        count(test_run, is_skipped),
+        timestamp,
+    );
+
+    for test in &test_run.tests {
+        let (classname, name) = test.name.rsplit_once("::").unwrap_or(("", &test.name));
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" file=\"{}\" line=\"{}\"",
+            escape(name),
+            escape(classname),
+            escape(&test.filepath.display().to_string()),
+            test.line,
+        );
+
+        match &test.state {
+            TestState::Passed => xml.push_str("/>\n"),
+            TestState::Skipped { reason } => {
+                xml.push_str(">\n");
+                match reason {
+                    Some(reason) => {
+                        let _ = writeln!(xml, "      <skipped message=\"{}\"/>", escape(reason));
+                    }
+                    None => xml.push_str("      <skipped/>\n"),
+                }
+                xml.push_str("    </testcase>\n");
+            }
+            TestState::Failed => {
+                xml.push_str(">\n");
+                let logs = test_logs
+                    .get(&test.name)
+                    .map(|lines| lines.join("\n"))
+                    .unwrap_or_default();
+                let message = match failure_reason(test_run, &test.name) {
+                    Some(reason) => format!("Test failed. Reason: {reason}"),
+                    None => "Test failed.".to_string(),
+                };
+                let _ = writeln!(
+                    xml,
+                    "      <failure message=\"{}\">{}</failure>",
+                    escape(&message),
+                    escape(&logs)
+                );
+                xml.push_str("    </testcase>\n");
+            }
+        }
+    }
+
+    xml.push_str("  </testsuite>\n");
+}
+
+/// Returns the failure reason that is stored for the given test in the data of the test run.
+fn failure_reason<'a>(test_run: &'a TestRun, test_name: &str) -> Option<&'a str> {
+    test_run
+        .data
+        .as_ref()?
+        .get("failures")?
+        .get(test_name)?
+        .get("reason")?
+        .as_str()
+}
+
+fn count(test_run: &TestRun, predicate: fn(&Test) -> bool) -> usize {
+    test_run.tests.iter().filter(|test| predicate(test)).count()
+}
+
+fn is_failed(test: &Test) -> bool {
+    test.state == TestState::Failed
+}
+
+fn is_skipped(test: &Test) -> bool {
+    matches!(test.state, TestState::Skipped { .. })
+}
+
+/// Escapes the given text for XML.
+///
+/// Characters that are not allowed in XML 1.0 (e.g. control characters in defmt output)
+/// are replaced with U+FFFD.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {
+                escaped.push(char::REPLACEMENT_CHARACTER)
+            }
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use mantra_schema::coverage::{CoverageSchema, Test, TestRun, TestState};
+
+    use super::{escape, junit_from_coverage};
+
+    fn test(name: &str, state: TestState) -> Test {
+        Test {
+            name: name.to_string(),
+            filepath: PathBuf::from("tests/emb.rs"),
+            line: 1,
+            state,
+            covered_files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn testcases_per_state() {
+        let coverage = CoverageSchema {
+            version: None,
+            test_runs: vec![TestRun {
+                name: "tests/<emb>".to_string(),
+                date: time::OffsetDateTime::UNIX_EPOCH,
+                nr_of_tests: 3,
+                data: None,
+                logs: None,
+                tests: vec![
+                    test("emb::tests::passing", TestState::Passed),
+                    test("emb::tests::failing", TestState::Failed),
+                    test(
+                        "emb::tests::ignored",
+                        TestState::Skipped {
+                            reason: Some("not \"ready\"".to_string()),
+                        },
+                    ),
+                ],
+            }],
+        };
+
+        let xml = junit_from_coverage(&coverage);
+
+        assert!(
+            xml.contains("tests=\"3\" failures=\"1\" skipped=\"1\""),
+            "Wrong test counts."
+        );
+        assert!(
+            xml.contains("<testsuite name=\"tests/&lt;emb&gt;\""),
+            "Run name not escaped."
+        );
+        assert!(
+            xml.contains("<testcase name=\"passing\" classname=\"emb::tests\""),
+            "Test name not split into classname and name."
+        );
+        assert!(
+            xml.contains("<skipped message=\"not &quot;ready&quot;\"/>"),
+            "Skip reason missing."
+        );
+        assert!(
+            xml.contains("<failure message=\"Test failed.\">"),
+            "Failure missing."
+        );
+    }
+
+    #[test]
+    fn invalid_xml_characters() {
+        assert_eq!(
+            escape("adc\u{0}\u{8}\u{b}\u{c}\u{1b}[0m <ok>\tdone\n"),
+            "adc\u{fffd}\u{fffd}\u{fffd}\u{fffd}\u{fffd}[0m &lt;ok&gt;\tdone\n"
+        );
+    }
+}
@@ -1,39 +1,64 @@
 use std::{
-    net::{Ipv4Addr, SocketAddrV4, TcpStream},
     path::{Path, PathBuf},
-    process::Stdio,
-    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
 };
 
-use cfg::{CliConfig, ResolvedConfig, RunCmdConfig, RunnerConfig};
-use covcon::cfg::DataFormat;
+use backend::{Backend, ConfiguredBackend, TargetStop};
+use cfg::{CliConfig, ResolvedConfig, RunCmdConfig, Timeouts};
 use coverage::CoverageError;
-use defmt_json_schema::v1::JsonFrame;
+use defmt::{DefmtLogs, EndConditions, ReadEnd};
+use output::{OutputSettings, RunStatus};
 use path_clean::PathClean;
-use serde_json::json;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use sink::RunSinks;
+use tokio_util::sync::CancellationToken;
 
+pub mod backend;
 pub mod cfg;
 pub mod collect;
 pub mod coverage;
+pub mod decode;
 pub mod defmt;
+pub mod gdb;
+pub mod junit;
+pub mod lock;
+pub mod matrix;
+pub mod output;
 pub mod path;
+pub mod process;
+pub mod remote;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod sink;
 
 pub const DEFAULT_RTT_PORT: u16 = 19021;
 
-/// Timeout defines the maximum duration to setup RTT connection between host and target
+/// Default timeout defining the maximum duration to setup RTT connection between host and target
 pub const SETUP_RTT_TIMEOUT_SEC: u64 = 60;
-/// Timeout defines the maximum duration of one test run
+/// Default timeout defining the maximum duration of one test run
 pub const EXECUTION_TIMEOUT_SEC: u64 = 3600; // 1h
+/// Default timeout defining the maximum duration to wait for a probe that is used by another run
+pub const PROBE_LOCK_TIMEOUT_SEC: u64 = 600; // 10min
 
 #[derive(Debug, thiserror::Error)]
 pub enum RunnerError {
-    #[error("Timeout waiting for rtt connection to start.")]
-    RttTimeout,
+    #[error("Timeout while {}.", .0)]
+    Timeout(RunPhase),
+    #[error("Execution did not finish within {} seconds.", .0.as_secs())]
+    ExecutionTimeout(Duration),
+    #[error("Failed to connect to RTT. Cause: {}", .0)]
+    Rtt(String),
     #[error("Error from gdb: {}", .0)]
     Gdb(String),
-    #[error("Error setting up the gdb script: {}", .0)]
-    GdbScript(String),
+    #[error("Failed starting QEMU. Cause: {}", .0)]
+    Qemu(String),
+    #[error("Error setting up the gdb commands: {}", .0)]
+    GdbCommands(String),
+    #[error("Could not connect to the target. Cause: {}", .0)]
+    Connection(String),
+    #[error("No debug probe found. Cause: {}", .0)]
+    NoDevice(String),
+    #[error("Could not load the binary onto the target. Cause: {}", .0)]
+    Load(String),
     #[error("{}", .0)]
     Config(#[from] cfg::ConfigError),
     #[error("Error reading defmt logs: {}", .0)]
@@ -46,6 +71,67 @@ pub enum RunnerError {
     PostRunner(String),
     #[error("Could not create coverage data. Cause: {}", .0)]
     Coverage(CoverageError),
+    #[error("{} test(s) failed: {}", .0.len(), .0.join(", "))]
+    TestsFailed(Vec<String>),
+    #[error("Run was interrupted.")]
+    Interrupted,
+    #[error("Probe '{}' is still used by another run.", .0)]
+    ProbeBusy(String),
+    #[error("Remote run failed. Cause: {}", .0)]
+    Remote(String, i32),
+}
+
+/// Phases to set up one test run on the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunPhase {
+    /// The backend starts its server, connects to the target, and loads the binary.
+    Flashing,
+    /// The binary is loaded, and the runner waits for the RTT connection.
+    RttConnect,
+}
+
+impl std::fmt::Display for RunPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunPhase::Flashing => write!(f, "flashing the binary"),
+            RunPhase::RttConnect => write!(f, "connecting to RTT"),
+        }
+    }
+}
+
+/// Exit code if at least one test failed.
+pub const EXIT_CODE_TEST_FAILURE: i32 = 1;
+/// Exit code if the runner could not communicate with the target (e.g. GDB or RTT errors).
+pub const EXIT_CODE_INFRASTRUCTURE: i32 = 2;
+/// Exit code if the runner configuration is invalid.
+pub const EXIT_CODE_CONFIGURATION: i32 = 3;
+/// Exit code if the run was interrupted by SIGINT or SIGTERM.
+pub const EXIT_CODE_INTERRUPTED: i32 = 130;
+
+impl RunnerError {
+    /// Returns the process exit code that represents this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunnerError::TestsFailed(_) => EXIT_CODE_TEST_FAILURE,
+            RunnerError::Interrupted => EXIT_CODE_INTERRUPTED,
+            RunnerError::Config(_) | RunnerError::GdbCommands(_) => EXIT_CODE_CONFIGURATION,
+            RunnerError::Timeout(_)
+            | RunnerError::ExecutionTimeout(_)
+            | RunnerError::Rtt(_)
+            | RunnerError::Gdb(_)
+            | RunnerError::Qemu(_)
+            | RunnerError::Connection(_)
+            | RunnerError::NoDevice(_)
+            | RunnerError::Load(_)
+            | RunnerError::Defmt(_)
+            | RunnerError::Setup(_)
+            | RunnerError::PreRunner(_)
+            | RunnerError::PostRunner(_)
+            | RunnerError::Coverage(_)
+            | RunnerError::ProbeBusy(_) => EXIT_CODE_INFRASTRUCTURE,
+            RunnerError::Remote(_, exit_code) => *exit_code,
+        }
+    }
 }
 
 pub async fn run(cli_cfg: CliConfig) -> Result<(), RunnerError> {
@@ -55,49 +141,62 @@ pub async fn run(cli_cfg: CliConfig) -> Result<(), RunnerError> {
             run_cmd(&cfg, run_cfg).await
         }
         cfg::Cmd::Collect(collect_cfg) => collect::run(collect_cfg).await,
+        cfg::Cmd::Decode(decode_cfg) => {
+            let cfg = cfg::get_cfg(&decode_cfg.runner_cfg, cli_cfg.verbose)?;
+            decode::run(&cfg, decode_cfg).await
+        }
+        cfg::Cmd::Agent(agent_cfg) => {
+            let cfg = cfg::get_cfg(&agent_cfg.runner_cfg, cli_cfg.verbose)?;
+            remote::agent(cfg, agent_cfg).await
+        }
+        cfg::Cmd::StopServer => {
+            let workspace_dir = path::get_cargo_root().map_err(cfg::ConfigError::from)?;
+            backend::openocd_server::stop_server(&workspace_dir.join(".embedded")).await
+        }
     }
 }
 
 pub async fn run_cmd(main_cfg: &ResolvedConfig, run_cfg: RunCmdConfig) -> Result<(), RunnerError> {
-    let output_dir = match run_cfg.output_dir {
-        Some(dir) => dir,
-        None => {
-            let mut dir = run_cfg.binary.clone();
-            dir.set_file_name(format!(
-                "{}_runner",
-                run_cfg
-                    .binary
-                    .file_name()
-                    .expect("Binary name must be a valid filename.")
-                    .to_string_lossy()
-            ));
-            dir
-        }
-    };
-
-    if !output_dir.exists() {
-        tokio::fs::create_dir_all(&output_dir)
-            .await
-            .map_err(|err| {
-                RunnerError::Setup(format!(
-                    "Could not create directory '{}'. Cause: {}",
-                    output_dir.display(),
-                    err
-                ))
-            })?;
+    if let Some(agent) = run_cfg.remote.clone() {
+        remote::run(main_cfg, run_cfg, &agent).await
+    } else if run_cfg.boards.is_empty() {
+        run_binary(main_cfg, run_cfg, RunControl::default()).await
+    } else {
+        matrix::run(main_cfg, run_cfg).await
     }
+}
 
-    let log_filepath = output_dir.join("defmt.log");
+/// Runs the binary on the board set in the run configuration.
+pub async fn run_binary(
+    main_cfg: &ResolvedConfig,
+    run_cfg: RunCmdConfig,
+    control: RunControl,
+) -> Result<(), RunnerError> {
+    let main_cfg = &main_cfg.for_run(&run_cfg)?;
+    let timeouts = main_cfg.runner_cfg.timeouts(&run_cfg.binary, &run_cfg);
+    let end_conditions = main_cfg.runner_cfg.end_conditions(&timeouts, &run_cfg)?;
+
+    let output_dir = run_cfg
+        .output_dir
+        .unwrap_or(output::default_output_dir(&run_cfg.binary));
+
+    output::create_output_dir(&output_dir).await?;
+
+    // held until the run is done, so parallel runs do not fight over the same probe
+    let _probe_lock = match main_cfg.runner_cfg.probe_key() {
+        Some(key) => Some(
+            lock::ProbeLock::acquire(
+                &main_cfg.embedded_dir,
+                &key,
+                main_cfg.runner_cfg.probe_lock_timeout(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
 
     let binary_str = run_cfg.binary.display().to_string();
-    let rel_binary_path = run_cfg
-        .binary
-        .strip_prefix(
-            crate::path::get_cargo_root().unwrap_or(std::env::current_dir().unwrap_or_default()),
-        )
-        .map(|p| p.to_path_buf())
-        .unwrap_or(run_cfg.binary.clone());
-    let rel_binary_str = rel_binary_path.display().to_string();
+    let rel_binary_path = output::relative_binary_path(&run_cfg.binary);
 
     #[cfg(target_os = "windows")]
     let pre_command = main_cfg
@@ -136,200 +235,88 @@ pub async fn run_cmd(main_cfg: &ResolvedConfig, run_cfg: RunCmdConfig) -> Result
         }
     }
 
-    let gdb_script = main_cfg
-        .runner_cfg
-        .gdb_script(
-            &run_cfg.binary,
-            &output_dir,
-            run_cfg.segger_gdb.unwrap_or(main_cfg.runner_cfg.segger_gdb),
-        )
-        .map_err(|_err| RunnerError::GdbScript(String::new()))?;
-
-    let gdb_script_file = output_dir.join("embedded.gdb");
-    tokio::fs::write(&gdb_script_file, gdb_script)
-        .await
-        .map_err(|err| RunnerError::GdbScript(err.to_string()))?;
-
-    let (defmt_frames, gdb_result) = run_gdb_sequence(
-        run_cfg.binary,
-        &main_cfg.workspace_dir,
-        &gdb_script_file,
-        &main_cfg.runner_cfg,
-    )
-    .await?;
-    let gdb_status = gdb_result?;
-
-    if !gdb_status.success() {
-        return Err(RunnerError::Gdb(format!(
-            "GDB did not run successfully. Exit code: '{gdb_status}'"
-        )));
-    }
-
-    println!("------------------ Output ------------------");
-
-    if defmt_frames.is_empty() {
-        println!("No logs received.");
-    } else {
-        let log_file = tokio::fs::File::create(&log_filepath)
-            .await
-            .map_err(|err| {
-                RunnerError::Setup(format!(
-                    "Could not create file '{}'. Cause: {}",
-                    log_filepath.display(),
-                    err
-                ))
-            })?;
-        let mut writer = BufWriter::new(log_file);
-
-        for frame in &defmt_frames {
-            let _w = writer
-                .write_all(
-                    serde_json::to_string(frame)
-                        .expect("DefmtFrame is valid JSON.")
-                        .as_bytes(),
-                )
-                .await;
-            let _w = writer.write_all("\n".as_bytes()).await;
-        }
-
-        let _f = writer.flush().await;
-
-        println!("Logs written to '{}'.", log_filepath.display());
-
-        let run_name = run_cfg
-            .run_name
-            .unwrap_or(rel_binary_path.display().to_string());
-
-        let data_path = run_cfg
-            .data_filepath
-            .or(main_cfg.runner_cfg.data_filepath.clone())
-            .unwrap_or(main_cfg.embedded_dir.join("test_run_data.json"));
-
-        let mut data = if data_path.exists() {
-            let data_content = tokio::fs::read_to_string(&data_path).await.map_err(|err| {
-                RunnerError::Setup(format!(
-                    "Could not read custom test run data '{}'. Cause: {}",
-                    data_path.display(),
-                    err
-                ))
-            })?;
-
-            let mut data: serde_json::Map<String, serde_json::Value> =
-                serde_json::from_str(&data_content).map_err(|err| {
-                    RunnerError::Setup(format!(
-                        "Could not deserialize metadata '{}'. Cause: {}",
-                        data_path.display(),
-                        err
-                    ))
-                })?;
-
-            data.insert(
-                "binary".to_string(),
-                serde_json::Value::String(rel_binary_str),
-            );
-
-            serde_json::Value::Object(data)
-        } else {
-            json!({
-                "binary": rel_binary_str
-            })
-        };
-
-        if let Some(extern_cov) = &main_cfg.runner_cfg.extern_coverage {
-            match (tokio::fs::read_to_string(&extern_cov.filepath).await, covcon::cfg::DataFormat::try_from(extern_cov.filepath.extension())) {
-                (Ok(content), Ok(DataFormat::Xml)) => {
-                    let cov_cfg = covcon::cfg::ConversionConfig {
-                        in_fmt: extern_cov.format,
-                        in_content: content,
-                        in_data_fmt: DataFormat::Xml,
-                        out_fmt: covcon::format::CoverageFormat::CoberturaV4,
-                        out_data_fmt: DataFormat::Json,
-                    };
+    let backend = ConfiguredBackend::new(
+        main_cfg,
+        &run_cfg.binary,
+        &output_dir,
+        run_cfg.segger_gdb.unwrap_or(main_cfg.runner_cfg.segger_gdb),
+    )?;
+    let skip_collect = control.skip_collect;
+    let settings = RunSettings {
+        binary: run_cfg.binary,
+        workspace_dir: main_cfg.workspace_dir.clone(),
+        timeouts,
+        end_conditions,
+        output_dir: output_dir.clone(),
+        control,
+    };
 
-                    match covcon::convert::convert_to_json(&cov_cfg) {
-                        Ok(json_cov) => {
-                            let meta_map = data.as_object_mut().expect("Meta is created as object above.");
-                            meta_map.insert("coverage".to_string(), json_cov);
-                        },
-                        Err(err) => log::error!("Failed extracting external coverage data. External coverage will be ignored. Cause: {err}"),
-                    }
-                }
-                (Err(err), _) => log::error!("Failed to read external coverage file. External coverage will be ignored. Cause: {err}"),
-                (_, _) => log::error!("Coverage file must be XML. External coverage will be ignored."),
-            }
+    let outcome = match backend {
+        ConfiguredBackend::OpenOcd(backend) => run_backend(backend, settings).await,
+        ConfiguredBackend::OpenOcdServer(backend) => run_backend(backend, settings).await,
+        ConfiguredBackend::GdbServer(backend) => run_backend(backend, settings).await,
+        ConfiguredBackend::Segger(backend) => run_backend(backend, settings).await,
+        ConfiguredBackend::JLink(backend) => run_backend(*backend, settings).await,
+        ConfiguredBackend::Qemu(backend) => run_backend(*backend, settings).await,
+        ConfiguredBackend::PyOcd(backend) => run_backend(*backend, settings).await,
+    };
+    let outcome = match outcome {
+        Ok(outcome) => outcome,
+        Err(RunnerError::Interrupted) => {
+            run_post_runner(main_cfg, &binary_str).await?;
+            return Err(RunnerError::Interrupted);
         }
+        Err(err) => return Err(err),
+    };
 
-        let logs =
-            serde_json::to_string(&defmt_frames).expect("DefmtFrames were deserialized before.");
-
-        let coverage = coverage::coverage_from_defmt_frames(
-            run_name,
-            Some(data),
-            defmt_frames.as_slice(),
-            Some(logs),
-        )
-        .map_err(RunnerError::Coverage)?;
-
-        // If no tests were found, execution most likely `cargo run` or `cargo bench` => no test coverage
-        if coverage
-            .test_runs
-            .iter()
-            .any(|test_run| test_run.nr_of_tests > 0)
+    let run_result = match outcome.result {
+        // target is stopped on test timeouts, completion, and interrupts => exit status is irrelevant
+        // e.g. QEMU exits on completion if the binary uses semihosting to exit
+        Err(RunnerError::Gdb(_))
+            if matches!(
+                outcome.defmt_logs.end,
+                ReadEnd::TestTimeout | ReadEnd::Completed(_)
+            ) || outcome.status.interrupted =>
         {
-            let coverage_file = output_dir.join("coverage.json");
-            tokio::fs::write(
-                &coverage_file,
-                serde_json::to_string(&coverage).expect("Coverage schema is valid JSON."),
-            )
-            .await
-            .map_err(|err| {
-                RunnerError::Setup(format!(
-                    "Could not write to file '{}'. Cause: {}",
-                    coverage_file.display(),
-                    err
-                ))
-            })?;
+            Ok(())
+        }
+        result => result,
+    };
 
-            println!("Coverage written to '{}'.", coverage_file.display());
+    let output_settings = OutputSettings {
+        output_dir,
+        rel_binary: rel_binary_path,
+        run_name: run_cfg.run_name,
+        data_filepath: run_cfg.data_filepath,
+        junit: run_cfg.junit.unwrap_or(main_cfg.runner_cfg.junit),
+        register: !skip_collect,
+    };
+    let failed_tests = output::store_results(
+        main_cfg,
+        &output_settings,
+        outcome.defmt_logs,
+        outcome.status,
+    )
+    .await?;
 
-            let coverages_filepath = coverage::coverages_filepath();
+    // received frames are stored before errors are returned to help debugging
+    run_result?;
 
-            if !coverages_filepath.exists() {
-                let _w =
-                    tokio::fs::write(coverages_filepath, coverage_file.display().to_string()).await;
-            } else {
-                let mut file = tokio::fs::OpenOptions::new()
-                    .append(true)
-                    .read(true)
-                    .open(coverages_filepath)
-                    .await
-                    .expect("Coverages file exists.");
-
-                let mut content = String::new();
-                file.read_to_string(&mut content)
-                    .await
-                    .expect("Reading coverages");
-
-                let mut exists = false;
-                for line in content.lines() {
-                    if line == coverage_file.display().to_string() {
-                        exists = true;
-                        break;
-                    }
-                }
+    run_post_runner(main_cfg, &binary_str).await?;
 
-                if !exists {
-                    let _w = file.write_all("\n".as_bytes()).await;
-                    let _w = file
-                        .write_all(coverage_file.display().to_string().as_bytes())
-                        .await;
-                }
+    if outcome.status.interrupted {
+        return Err(RunnerError::Interrupted);
+    }
 
-                let _f = file.flush().await;
-            }
-        }
+    if !failed_tests.is_empty() {
+        return Err(RunnerError::TestsFailed(failed_tests));
     }
 
+    Ok(())
+}
+
+/// Runs the post-runner command of the configuration, if one is set.
+async fn run_post_runner(main_cfg: &ResolvedConfig, binary_str: &str) -> Result<(), RunnerError> {
     #[cfg(target_os = "windows")]
     let post_command = main_cfg
         .runner_cfg
@@ -342,7 +329,7 @@ pub async fn run_cmd(main_cfg: &ResolvedConfig, run_cfg: RunCmdConfig) -> Result
     if let Some(post_command) = post_command {
         println!("-------------------- Post Runner --------------------");
         let mut args = post_command.args.clone();
-        args.push(binary_str);
+        args.push(binary_str.to_string());
 
         let output = tokio::process::Command::new(&post_command.name)
             .args(args)
@@ -370,115 +357,303 @@ pub async fn run_cmd(main_cfg: &ResolvedConfig, run_cfg: RunCmdConfig) -> Result
     Ok(())
 }
 
-pub async fn run_gdb_sequence(
-    binary: PathBuf,
-    workspace_dir: &Path,
-    tmp_gdb_file: &Path,
-    runner_cfg: &RunnerConfig,
-) -> Result<
-    (
-        Vec<JsonFrame>,
-        Result<std::process::ExitStatus, RunnerError>,
-    ),
-    RunnerError,
-> {
-    let mut gdb_cmd = tokio::process::Command::new(
-        std::env::var("GDB").unwrap_or("arm-none-eabi-gdb".to_string()),
-    );
-    let mut gdb = gdb_cmd
-        .args([
-            "-x",
-            &tmp_gdb_file.to_string_lossy(),
-            &binary.to_string_lossy(),
-        ])
-        .current_dir(workspace_dir)
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap();
+/// Settings to run one binary on a backend.
+#[derive(Debug)]
+pub struct RunSettings {
+    pub binary: PathBuf,
+    pub workspace_dir: PathBuf,
+    pub timeouts: Timeouts,
+    pub end_conditions: EndConditions,
+    /// Directory to store the raw RTT data, logs, and the logs of the backend.
+    pub output_dir: PathBuf,
+    pub control: RunControl,
+}
+
+/// Lets the caller of a run observe and stop it (e.g. the agent for remote runs).
+#[derive(Debug, Clone, Default)]
+pub struct RunControl {
+    /// Receives every decoded frame.
+    pub frames: Option<sink::FrameSender>,
+    /// Interrupts the run like SIGINT once cancelled.
+    pub interrupt: CancellationToken,
+    /// `true`: The coverage file is not registered for the `collect` command,
+    /// because the output directory is removed after the run (e.g. by the agent).
+    pub skip_collect: bool,
+}
+
+/// Runs the binary on the given backend, and reads the defmt frames of the target while it runs.
+pub async fn run_backend<B: Backend>(
+    mut backend: B,
+    settings: RunSettings,
+) -> Result<RunOutcome, RunnerError> {
+    let RunSettings {
+        binary,
+        workspace_dir,
+        timeouts,
+        end_conditions,
+        output_dir,
+        control,
+    } = settings;
 
     println!("-------------------- Communication Setup --------------------");
 
-    let rtt_port = runner_cfg.rtt_port.unwrap_or(DEFAULT_RTT_PORT);
-    let stream = tokio::time::timeout(
-        std::time::Duration::from_secs(SETUP_RTT_TIMEOUT_SEC),
-        tokio::spawn(async move {
-            loop {
-                match TcpStream::connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), rtt_port)) {
-                    Ok(stream) => {
-                        return Ok(stream);
-                    }
-                    Err(err)
-                        if matches!(
-                            err.kind(),
-                            std::io::ErrorKind::TimedOut | std::io::ErrorKind::ConnectionRefused
-                        ) =>
-                    {
-                        std::thread::sleep(std::time::Duration::from_millis(50));
-                    }
-                    Err(err) => {
-                        return Err(err);
-                    }
-                }
-            }
-        }),
-    )
-    .await;
+    // created before the setup, so logs of a previous run do not remain if no frame arrives
+    let sinks = match RunSinks::create(&output_dir).await {
+        Ok(sinks) => sinks.forward_to(control.frames),
+        Err(err) => {
+            let _ = backend.teardown().await;
+            return Err(RunnerError::Setup(err.to_string()));
+        }
+    };
+
+    let interrupt = interrupt_token(&control.interrupt);
+    let mut phase = RunPhase::Flashing;
+    let setup = tokio::select! {
+        setup = tokio::time::timeout(timeouts.setup, setup_backend(&mut backend, &mut phase)) => Some(setup),
+        _ = interrupt.cancelled() => None,
+    };
 
-    let stream = match stream {
-        Ok(Ok(Ok(stream))) => stream,
-        Ok(Ok(Err(io_err))) => {
-            log::error!("Failed to connect to RTT. Cause: {io_err}");
-            let _ = gdb.kill().await;
-            return Err(RunnerError::RttTimeout);
+    let stream = match setup {
+        Some(Ok(Ok(stream))) => stream,
+        None => {
+            // no frames were received yet => nothing to store
+            let _ = backend.teardown().await;
+            return Err(RunnerError::Interrupted);
+        }
+        Some(Ok(Err(err))) => {
+            let _ = backend.teardown().await;
+            return Err(err);
         }
-        _ => {
-            log::error!("Timeout while trying to connect to RTT.");
-            let _ = gdb.kill().await;
-            return Err(RunnerError::RttTimeout);
+        Some(Err(_)) => {
+            let _ = backend.teardown().await;
+            return Err(RunnerError::Timeout(phase));
         }
     };
 
     println!();
     println!("-------------------- Running --------------------");
 
-    // start defmt thread + end-signal
-    let end_signal = Arc::new(AtomicBool::new(false));
+    // start defmt task + end-signal
+    let end_signal = CancellationToken::new();
     let thread_signal = end_signal.clone();
-    let workspace_root = workspace_dir.to_path_buf();
-    let defmt_thread = tokio::spawn(async move {
-        defmt::read_defmt_frames(&binary, &workspace_root, stream, thread_signal)
+    let mut defmt_thread = tokio::spawn(async move {
+        defmt::read_defmt_frames(
+            &binary,
+            &workspace_dir,
+            stream,
+            thread_signal,
+            &end_conditions,
+            Some(&output_dir.join("rtt.bin")),
+            sinks,
+        )
+        .await
     });
 
-    // wait for gdb to end
-    let gdb_result = match tokio::time::timeout(
-        std::time::Duration::from_secs(EXECUTION_TIMEOUT_SEC),
-        gdb.wait(),
-    )
-    .await
-    {
-        Ok(Ok(status)) => Ok(status),
-        Ok(Err(err)) => Err(RunnerError::Gdb(format!(
-            "Error waiting for gdb to finish. Cause: {err}"
-        ))),
+    // wait for the target to stop, or stop it if the run completed, a test did not finish in time, or the runner got interrupted
+    let stop = CancellationToken::new();
+    let mut defmt_result = None;
+    let mut status = RunStatus::default();
+    let execution = tokio::time::timeout(timeouts.execution, async {
+        let run = backend.run(stop.clone());
+        tokio::pin!(run);
+
+        loop {
+            tokio::select! {
+                target_stop = &mut run => return target_stop,
+                result = &mut defmt_thread, if defmt_result.is_none() => {
+                    let end = match &result {
+                        Ok((_, Ok(end))) => Some(*end),
+                        _ => None,
+                    };
+                    defmt_result = Some(result);
+
+                    match end {
+                        Some(ReadEnd::TestTimeout) => {
+                            log::error!("Test did not finish within the test timeout.");
+                        }
+                        Some(ReadEnd::Completed(marker)) => {
+                            log::info!("Run completed on {marker}.");
+                        }
+                        _ => continue,
+                    }
+
+                    stop.cancel();
+                }
+                _ = interrupt.cancelled(), if !status.interrupted => {
+                    status.interrupted = true;
+                    stop.cancel();
+                }
+            }
+        }
+    })
+    .await;
+
+    let backend_result = match execution {
+        Ok(Ok(target_stop)) => {
+            match target_stop {
+                TargetStop::HardFault => {
+                    log::error!("Target stopped in the HardFault handler.");
+                    status.hard_fault = true;
+                }
+                TargetStop::NotHalted => {
+                    log::warn!("Target could not be interrupted.");
+                }
+                TargetStop::Stopped | TargetStop::Halted => {}
+            }
+
+            backend.teardown().await
+        }
+        Ok(Err(err)) => {
+            let _ = backend.teardown().await;
+            Err(err)
+        }
         Err(_) => {
-            log::error!("Timeout while waiting for gdb to end.");
-            let _ = gdb.kill().await;
-            return Err(RunnerError::RttTimeout);
+            log::error!("Execution did not finish within the execution timeout.");
+            let _ = backend.teardown().await;
+            status.execution_timeout = true;
+            Err(RunnerError::ExecutionTimeout(timeouts.execution))
         }
     };
 
     // signal defmt end
-    end_signal.store(true, std::sync::atomic::Ordering::Relaxed);
+    end_signal.cancel();
 
     // join defmt thread to get logs
-    let defmt_result = defmt_thread
-        .await
-        .map_err(|_| RunnerError::Defmt("Failed waiting for defmt logs.".to_string()))?;
+    let (sinks, defmt_result) = match defmt_result {
+        Some(result) => result,
+        None => defmt_thread.await,
+    }
+    .map_err(|_| RunnerError::Defmt("Failed waiting for defmt logs.".to_string()))?;
+
+    // frames received before a decoding error are kept
+    let (end, result) = match defmt_result {
+        Ok(end) => (end, backend_result),
+        Err(err) => (
+            ReadEnd::Failed,
+            backend_result.and(Err(RunnerError::Defmt(format!(
+                "Failed extracting defmt logs. Cause: {err}"
+            )))),
+        ),
+    };
+
+    Ok(RunOutcome {
+        defmt_logs: DefmtLogs { sinks, end },
+        result,
+        status,
+    })
+}
+
+/// Starts the server of the backend, flashes the binary, and connects to the log transport.
+async fn setup_backend<B: Backend>(
+    backend: &mut B,
+    phase: &mut RunPhase,
+) -> Result<B::LogStream, RunnerError> {
+    backend.start_server().await?;
+    backend.flash().await?;
+
+    *phase = RunPhase::RttConnect;
+    backend.setup_log_transport().await
+}
+
+/// Outcome of running a binary on the target.
+#[derive(Debug)]
+pub struct RunOutcome {
+    pub defmt_logs: DefmtLogs,
+    /// Result of executing the binary after the target was set up.
+    ///
+    /// Frames received until an error occured are still available in `defmt_logs`.
+    pub result: Result<(), RunnerError>,
+    pub status: RunStatus,
+}
+
+/// Interrupt of one run that is cancelled on the first SIGINT or SIGTERM.
+///
+/// Signals are no longer handled once dropped, so long-running processes (e.g. the agent) do not keep one listener per run.
+pub(crate) struct Interrupt {
+    token: CancellationToken,
+    listener: tokio::task::JoinHandle<()>,
+}
+
+impl Interrupt {
+    pub(crate) async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+}
+
+impl Drop for Interrupt {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+/// Cancels the returned interrupt on the first SIGINT or SIGTERM, and exits the process on the second one.
+///
+/// The returned interrupt is also cancelled once the given parent token is cancelled.
+pub(crate) fn interrupt_token(parent: &CancellationToken) -> Interrupt {
+    let token = parent.child_token();
+    let cancel = token.clone();
+
+    let listener = tokio::spawn(async move {
+        let mut signals = match ShutdownSignals::new() {
+            Ok(signals) => signals,
+            Err(err) => {
+                log::warn!("Could not listen for interrupts. Cause: {err}");
+                return;
+            }
+        };
+
+        signals.recv().await;
+        log::warn!("Interrupt received. Stopping the run. Interrupt again to exit immediately.");
+        cancel.cancel();
+
+        signals.recv().await;
+        // destructors do not run on exit, so children would keep the probe
+        process::kill_all();
+        std::process::exit(EXIT_CODE_INTERRUPTED);
+    });
+
+    Interrupt { token, listener }
+}
+
+/// Listens for SIGINT (Ctrl-C), and SIGTERM on unix.
+struct ShutdownSignals {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(windows)]
+    ctrl_c: tokio::signal::windows::CtrlC,
+}
+
+impl ShutdownSignals {
+    fn new() -> std::io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            Ok(Self {
+                interrupt: signal(SignalKind::interrupt())?,
+                terminate: signal(SignalKind::terminate())?,
+            })
+        }
+
+        #[cfg(windows)]
+        Ok(Self {
+            ctrl_c: tokio::signal::windows::ctrl_c()?,
+        })
+    }
 
-    let defmt_frames = defmt_result
-        .map_err(|err| RunnerError::Defmt(format!("Failed extracting defmt logs. Cause: {err}")))?;
+    /// Waits for the next signal.
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = self.interrupt.recv() => {}
+            _ = self.terminate.recv() => {}
+        }
 
-    Ok((defmt_frames, gdb_result))
+        #[cfg(windows)]
+        self.ctrl_c.recv().await;
+    }
 }
 
 /// Converts the given path into a cleaned absolute path.
@@ -495,3 +670,172 @@ pub fn absolute_path(path: &Path) -> std::io::Result<PathBuf> {
 
     Ok(absolute_path)
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        backend::{Backend, TargetStop},
+        cfg::Timeouts,
+        defmt::{EndConditions, ReadEnd},
+        RunControl, RunSettings, RunnerError,
+    };
+
+    /// Backend without target that sends no defmt frames.
+    struct FakeBackend {
+        flash_error: bool,
+        torn_down: Arc<AtomicBool>,
+    }
+
+    impl Backend for FakeBackend {
+        type LogStream = Cursor<Vec<u8>>;
+
+        async fn start_server(&mut self) -> Result<(), RunnerError> {
+            Ok(())
+        }
+
+        async fn flash(&mut self) -> Result<(), RunnerError> {
+            if self.flash_error {
+                Err(RunnerError::Load("No flash on fake target.".to_string()))
+            } else {
+                Ok(())
+            }
+        }
+
+        async fn setup_log_transport(&mut self) -> Result<Self::LogStream, RunnerError> {
+            Ok(Cursor::new(Vec::new()))
+        }
+
+        async fn run(&mut self, _stop: CancellationToken) -> Result<TargetStop, RunnerError> {
+            Ok(TargetStop::Stopped)
+        }
+
+        async fn teardown(self) -> Result<(), RunnerError> {
+            self.torn_down.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn settings(name: &str) -> RunSettings {
+        let workspace_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let output_dir = std::env::temp_dir().join(format!("emb-runner-{name}"));
+        std::fs::create_dir_all(&output_dir).unwrap();
+
+        RunSettings {
+            binary: workspace_dir.join("test_binaries/emb-runner-test"),
+            workspace_dir,
+            timeouts: Timeouts {
+                setup: Duration::from_secs(5),
+                execution: Duration::from_secs(5),
+                test: None,
+            },
+            end_conditions: EndConditions {
+                test_timeout: None,
+                completion_pattern: None,
+            },
+            output_dir,
+            control: RunControl::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn fake_backend_runs_to_end() {
+        let torn_down = Arc::new(AtomicBool::new(false));
+        let backend = FakeBackend {
+            flash_error: false,
+            torn_down: torn_down.clone(),
+        };
+
+        let outcome = super::run_backend(backend, settings("fake-backend-run"))
+            .await
+            .unwrap();
+
+        assert!(outcome.result.is_ok(), "Run failed.");
+        assert_eq!(
+            outcome.defmt_logs.end,
+            ReadEnd::ConnectionClosed,
+            "Empty log stream not closed."
+        );
+        assert!(torn_down.load(Ordering::SeqCst), "Backend not torn down.");
+    }
+
+    #[tokio::test]
+    async fn fake_backend_torn_down_on_setup_error() {
+        let torn_down = Arc::new(AtomicBool::new(false));
+        let backend = FakeBackend {
+            flash_error: true,
+            torn_down: torn_down.clone(),
+        };
+
+        let result = super::run_backend(backend, settings("fake-backend-flash")).await;
+
+        assert!(
+            matches!(result, Err(RunnerError::Load(_))),
+            "Flash error not returned."
+        );
+        assert!(torn_down.load(Ordering::SeqCst), "Backend not torn down.");
+    }
+
+    /// Runs the full flow from pre-runner to coverage against the simulated target.
+    #[cfg(feature = "sim")]
+    #[tokio::test]
+    #[ignore = "requires a GDB for ARM targets (e.g. `gdb-multiarch` set per `GDB`)"]
+    async fn sim_target_run_cmd() {
+        use clap::Parser;
+
+        let settings = settings("sim-target-run");
+        let rtt_data = std::fs::read(settings.binary.with_extension("rtt")).unwrap();
+        let sim = crate::sim::SimTarget::new(&settings.binary, rtt_data)
+            .unwrap()
+            .serve()
+            .await
+            .unwrap();
+        let rtt_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let runner_cfg = toml::from_str(&format!(
+            "
+            gdb-connection = \"{}\"
+            rtt-port = {rtt_port}
+
+            [pre-runner]
+            name = \"true\"
+            args = []
+            ",
+            sim.gdb_connection()
+        ))
+        .unwrap();
+        let main_cfg = crate::cfg::ResolvedConfig {
+            runner_cfg,
+            verbose: false,
+            workspace_dir: settings.workspace_dir.clone(),
+            embedded_dir: settings.output_dir.clone(),
+        };
+        let run_cfg = crate::cfg::RunCmdConfig::parse_from([
+            "run",
+            "--output-dir",
+            &settings.output_dir.display().to_string(),
+            &settings.binary.display().to_string(),
+        ]);
+
+        super::run_cmd(&main_cfg, run_cfg).await.unwrap();
+
+        let coverage = std::fs::read_to_string(settings.output_dir.join("coverage.json")).unwrap();
+        assert!(coverage.contains("req_a"), "Replayed trace not covered.");
+        assert!(coverage.contains("req_b"), "Replayed trace not covered.");
+    }
+}
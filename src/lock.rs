@@ -0,0 +1,115 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    path::Path,
+    time::Duration,
+};
+
+use crate::RunnerError;
+
+/// Delay between attempts to acquire a probe lock.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Advisory file lock that prevents runs in other processes from using the same probe.
+///
+/// The lock is released once this guard is dropped, or the process exits.
+#[derive(Debug)]
+pub struct ProbeLock {
+    _file: File,
+}
+
+impl ProbeLock {
+    /// Acquires the lock for the given probe key in `<embedded_dir>/locks/`.
+    ///
+    /// Waits until the lock is released by another run, or the timeout is reached.
+    pub async fn acquire(
+        embedded_dir: &Path,
+        key: &str,
+        timeout: Duration,
+    ) -> Result<Self, RunnerError> {
+        let lock_dir = embedded_dir.join("locks");
+        let setup_err = |err: std::io::Error| {
+            RunnerError::Setup(format!("Could not create probe lock. Cause: {err}"))
+        };
+
+        std::fs::create_dir_all(&lock_dir).map_err(setup_err)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_dir.join(format!("{}.lock", probe_filename(key))))
+            .map_err(setup_err)?;
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut waiting = false;
+
+        loop {
+            match file.try_lock() {
+                Ok(()) => {
+                    if waiting {
+                        log::info!("Probe '{key}' is free.");
+                    }
+                    return Ok(Self { _file: file });
+                }
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(err)) => return Err(setup_err(err)),
+            }
+
+            if !waiting {
+                log::info!(
+                    "Waiting for probe '{key}', which is used by another run (timeout: {}s).",
+                    timeout.as_secs()
+                );
+                waiting = true;
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                return Err(RunnerError::ProbeBusy(key.to_string()));
+            }
+            tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Converts the probe key into a valid filename.
+pub(crate) fn probe_filename(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::RunnerError;
+
+    use super::ProbeLock;
+
+    #[tokio::test]
+    async fn second_run_waits_for_probe() {
+        let embedded_dir = std::env::temp_dir().join("emb-runner-probe-lock");
+
+        let lock = ProbeLock::acquire(&embedded_dir, ".embedded/openocd.cfg", Duration::ZERO)
+            .await
+            .unwrap();
+        let busy = ProbeLock::acquire(
+            &embedded_dir,
+            ".embedded/openocd.cfg",
+            Duration::from_millis(300),
+        )
+        .await;
+
+        assert!(
+            matches!(busy, Err(RunnerError::ProbeBusy(_))),
+            "Probe not locked."
+        );
+
+        drop(lock);
+        assert!(
+            ProbeLock::acquire(&embedded_dir, ".embedded/openocd.cfg", Duration::ZERO)
+                .await
+                .is_ok(),
+            "Probe lock not released."
+        );
+    }
+}
@@ -17,6 +17,6 @@ async fn main() {
 
     if let Err(err) = embedded_runner::run(cfg).await {
         log::error!("Embedded runner failed: {err}");
-        std::process::exit(1);
+        std::process::exit(err.exit_code());
     }
 }
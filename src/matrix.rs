@@ -0,0 +1,453 @@
+//! Runs one binary on several boards, and combines the results of all boards.
+
+use std::path::Path;
+
+use mantra_schema::coverage::{CoverageSchema, TestState};
+use tokio::task::JoinSet;
+
+use crate::{
+    cfg::{BackendKind, ResolvedConfig, RttPort, RunCmdConfig, RunnerConfig},
+    junit, output, RunControl, RunnerError,
+};
+
+/// Runs the binary on all boards set with `--boards`.
+///
+/// The combined coverage of all boards is written to `coverage.json` in the output directory,
+/// and a matrix with the test results per board is printed.
+pub async fn run(main_cfg: &ResolvedConfig, run_cfg: RunCmdConfig) -> Result<(), RunnerError> {
+    let output_dir = run_cfg
+        .output_dir
+        .clone()
+        .unwrap_or(output::default_output_dir(&run_cfg.binary));
+    let run_name = run_cfg.run_name.clone().unwrap_or(
+        output::relative_binary_path(&run_cfg.binary)
+            .display()
+            .to_string(),
+    );
+
+    let mut board_runs = Vec::new();
+    let mut board_ports = Vec::new();
+    for board in &run_cfg.boards {
+        let mut board_run = run_cfg.clone();
+        board_run.boards = Vec::new();
+        board_run.board = Some(board.clone());
+        board_run.output_dir = Some(output_dir.join(board));
+        board_run.run_name = Some(format!("{run_name} ({board})"));
+
+        // unknown boards are detected before any board is run
+        let board_cfg = main_cfg.for_run(&board_run)?.runner_cfg;
+        board_ports.push(host_ports(
+            &board_cfg,
+            board_run.segger_gdb.unwrap_or(board_cfg.segger_gdb),
+        ));
+        // coverage of previous runs must not end up in the matrix if a run fails early
+        let _ = tokio::fs::remove_file(output_dir.join(board).join("coverage.json")).await;
+        board_runs.push(board_run);
+    }
+
+    let results = if run_cfg.parallel {
+        let mut board_runs: Vec<_> = board_runs.into_iter().map(Some).collect();
+        let mut runs = JoinSet::new();
+
+        for group in parallel_groups(&board_ports) {
+            if group.len() > 1 {
+                let boards: Vec<&str> = group
+                    .iter()
+                    .map(|index| run_cfg.boards[*index].as_str())
+                    .collect();
+                log::warn!(
+                    "Boards '{}' use the same ports on the host, so they run one after another.",
+                    boards.join("', '")
+                );
+            }
+
+            let group_runs: Vec<_> = group
+                .into_iter()
+                .map(|index| {
+                    (
+                        index,
+                        board_runs[index]
+                            .take()
+                            .expect("Every board is in exactly one group."),
+                    )
+                })
+                .collect();
+            let main_cfg = main_cfg.clone();
+            runs.spawn(async move {
+                let mut results = Vec::new();
+                for (index, board_run) in group_runs {
+                    results.push((
+                        index,
+                        crate::run_binary(&main_cfg, board_run, RunControl::default()).await,
+                    ));
+                }
+                results
+            });
+        }
+
+        let mut results: Vec<_> = runs.join_all().await.into_iter().flatten().collect();
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    } else {
+        let mut results = Vec::new();
+        for board_run in board_runs {
+            println!(
+                "==================== Board '{}' ====================",
+                board_run.board.as_deref().unwrap_or_default()
+            );
+            results.push(crate::run_binary(main_cfg, board_run, RunControl::default()).await);
+        }
+        results
+    };
+
+    let mut coverages = Vec::new();
+    for board in &run_cfg.boards {
+        coverages.push(read_coverage(&output_dir.join(board)).await);
+    }
+
+    println!("------------------ Boards ------------------");
+    print!("{}", format_matrix(&run_cfg.boards, &coverages, &results));
+
+    let combined = CoverageSchema {
+        version: Some(mantra_schema::SCHEMA_VERSION.to_string()),
+        test_runs: coverages
+            .into_iter()
+            .flatten()
+            .flat_map(|coverage| coverage.test_runs)
+            .collect(),
+    };
+    if !combined.test_runs.is_empty() {
+        write_combined(
+            &output_dir,
+            &combined,
+            run_cfg.junit.unwrap_or(main_cfg.runner_cfg.junit),
+        )
+        .await?;
+    }
+
+    combine_results(&run_cfg.boards, results)
+}
+
+/// Returns the fixed ports on the host that a run with the given configuration listens on, or connects to.
+///
+/// Ports set to `auto` are picked per run, so they are never shared.
+fn host_ports(runner_cfg: &RunnerConfig, segger_gdb: bool) -> Vec<u16> {
+    let mut ports: Vec<u16> = match (runner_cfg.backend, &runner_cfg.gdb_connection) {
+        (BackendKind::Qemu, _) => {
+            return runner_cfg
+                .qemu
+                .iter()
+                .flat_map(|qemu| [qemu.gdb_port(), qemu.log_port()])
+                .collect();
+        }
+        (BackendKind::PyOcd, _) => runner_cfg
+            .pyocd
+            .iter()
+            .map(|pyocd| pyocd.gdb_port())
+            .collect(),
+        // GDB server started outside the runner
+        (BackendKind::OpenOcd, Some(_)) => Vec::new(),
+        (BackendKind::OpenOcd, None) if segger_gdb => runner_cfg
+            .jlink
+            .iter()
+            .flat_map(|jlink| [jlink.gdb_port(), jlink.swo_port(), jlink.telnet_port()])
+            .collect(),
+        // OpenOCD started by GDB only opens the RTT server
+        (BackendKind::OpenOcd, None) => runner_cfg
+            .openocd_server
+            .iter()
+            .flat_map(|server| [server.gdb_port(), server.tcl_port()])
+            .collect(),
+    };
+
+    if let RttPort::Fixed(rtt_port) = runner_cfg.rtt_server_port() {
+        ports.push(rtt_port);
+    }
+
+    ports
+}
+
+/// Groups the boards that may run at the same time, given the host ports of every board.
+///
+/// Boards that share a port are in the same group, and must run one after another.
+fn parallel_groups(board_ports: &[Vec<u16>]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<(Vec<usize>, Vec<u16>)> = Vec::new();
+
+    for (index, ports) in board_ports.iter().enumerate() {
+        let (shared, mut separate): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .partition(|(_, group_ports)| ports.iter().any(|port| group_ports.contains(port)));
+
+        let mut merged = (vec![index], ports.clone());
+        for (boards, group_ports) in shared {
+            merged.0.extend(boards);
+            merged.1.extend(group_ports);
+        }
+        merged.0.sort();
+
+        separate.push(merged);
+        groups = separate;
+    }
+
+    groups.sort_by_key(|(boards, _)| boards[0]);
+    groups.into_iter().map(|(boards, _)| boards).collect()
+}
+
+/// Reads the coverage a run stored in the given output directory, if there is one.
+async fn read_coverage(output_dir: &Path) -> Option<CoverageSchema> {
+    let content = tokio::fs::read_to_string(output_dir.join("coverage.json"))
+        .await
+        .ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+async fn write_combined(
+    output_dir: &Path,
+    combined: &CoverageSchema,
+    junit: bool,
+) -> Result<(), RunnerError> {
+    let coverage_file = output_dir.join("coverage.json");
+    tokio::fs::write(
+        &coverage_file,
+        serde_json::to_string(combined).expect("Coverage schema is valid JSON."),
+    )
+    .await
+    .map_err(|err| {
+        RunnerError::Setup(format!(
+            "Could not write to file '{}'. Cause: {}",
+            coverage_file.display(),
+            err
+        ))
+    })?;
+    println!(
+        "Combined coverage written to '{}'.",
+        coverage_file.display()
+    );
+
+    if junit {
+        let junit_file = output_dir.join("junit.xml");
+        tokio::fs::write(&junit_file, junit::junit_from_coverage(combined))
+            .await
+            .map_err(|err| {
+                RunnerError::Setup(format!(
+                    "Could not write to file '{}'. Cause: {}",
+                    junit_file.display(),
+                    err
+                ))
+            })?;
+        println!(
+            "Combined JUnit report written to '{}'.",
+            junit_file.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Formats the test results per board as table with one row per test, and one column per board.
+///
+/// The last row contains the result of the run on each board.
+fn format_matrix(
+    boards: &[String],
+    coverages: &[Option<CoverageSchema>],
+    results: &[Result<(), RunnerError>],
+) -> String {
+    let mut tests: Vec<&str> = Vec::new();
+    for test_run in coverages.iter().flatten().flat_map(|c| c.test_runs.iter()) {
+        for test in &test_run.tests {
+            if !tests.contains(&test.name.as_str()) {
+                tests.push(&test.name);
+            }
+        }
+    }
+
+    let mut rows = vec![std::iter::once("test".to_string())
+        .chain(boards.iter().cloned())
+        .collect::<Vec<_>>()];
+    for test in tests {
+        let mut row = vec![test.to_string()];
+        for coverage in coverages {
+            let state = coverage
+                .iter()
+                .flat_map(|c| c.test_runs.iter())
+                .flat_map(|test_run| test_run.tests.iter())
+                .find(|t| t.name == test)
+                .map(|t| &t.state);
+            row.push(
+                match state {
+                    Some(TestState::Passed) => "pass",
+                    Some(TestState::Failed) => "FAIL",
+                    Some(TestState::Skipped { .. }) => "skip",
+                    None => "-",
+                }
+                .to_string(),
+            );
+        }
+        rows.push(row);
+    }
+    rows.push(
+        std::iter::once("run".to_string())
+            .chain(results.iter().map(|result| {
+                match result {
+                    Ok(()) => "ok",
+                    Err(RunnerError::TestsFailed(_)) => "FAIL",
+                    Err(_) => "ERROR",
+                }
+                .to_string()
+            }))
+            .collect(),
+    );
+
+    let widths: Vec<usize> = (0..=boards.len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+
+    let mut matrix = String::new();
+    for (index, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        matrix.push_str(cells.join(" | ").trim_end());
+        matrix.push('\n');
+
+        if index == 0 {
+            let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+            matrix.push_str(&separator.join("-+-"));
+            matrix.push('\n');
+        }
+    }
+
+    matrix
+}
+
+/// Combines the results of all boards into one result.
+///
+/// Errors that are not caused by failed tests take precedence over failed tests.
+fn combine_results(
+    boards: &[String],
+    results: Vec<Result<(), RunnerError>>,
+) -> Result<(), RunnerError> {
+    let mut failed_tests = Vec::new();
+    let mut run_error = None;
+
+    for (board, result) in boards.iter().zip(results) {
+        match result {
+            Ok(()) => {}
+            Err(RunnerError::TestsFailed(tests)) => {
+                failed_tests.extend(tests.into_iter().map(|test| format!("{test} ({board})")));
+            }
+            Err(err) => {
+                log::error!("Run on board '{board}' failed. Cause: {err}");
+
+                if run_error.is_none() || matches!(err, RunnerError::Interrupted) {
+                    run_error = Some(err);
+                }
+            }
+        }
+    }
+
+    match run_error {
+        Some(err) => Err(err),
+        None if !failed_tests.is_empty() => Err(RunnerError::TestsFailed(failed_tests)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use mantra_schema::coverage::{CoverageSchema, Test, TestRun, TestState};
+
+    use crate::RunnerError;
+
+    use crate::cfg::RunnerConfig;
+
+    use super::{format_matrix, host_ports, parallel_groups};
+
+    fn coverage(tests: &[(&str, TestState)]) -> CoverageSchema {
+        CoverageSchema {
+            version: None,
+            test_runs: vec![TestRun {
+                name: "tests/<emb>".to_string(),
+                date: time::OffsetDateTime::UNIX_EPOCH,
+                nr_of_tests: tests.len() as u32,
+                data: None,
+                logs: None,
+                tests: tests
+                    .iter()
+                    .map(|(name, state)| Test {
+                        name: name.to_string(),
+                        filepath: PathBuf::from("tests/emb.rs"),
+                        line: 1,
+                        state: state.clone(),
+                        covered_files: Vec::new(),
+                    })
+                    .collect(),
+            }],
+        }
+    }
+
+    #[test]
+    fn matrix_per_board() {
+        let boards = [
+            "rev-a".to_string(),
+            "rev-b".to_string(),
+            "rev-c".to_string(),
+        ];
+        let coverages = [
+            Some(coverage(&[
+                ("blink", TestState::Passed),
+                ("adc", TestState::Passed),
+            ])),
+            Some(coverage(&[
+                ("blink", TestState::Passed),
+                ("adc", TestState::Failed),
+            ])),
+            None,
+        ];
+        let results = [
+            Ok(()),
+            Err(RunnerError::TestsFailed(vec!["adc".to_string()])),
+            Err(RunnerError::NoDevice("No probe.".to_string())),
+        ];
+
+        assert_eq!(
+            format_matrix(&boards, &coverages, &results),
+            "test  | rev-a | rev-b | rev-c
+------+-------+-------+------
+blink | pass  | pass  | -
+adc   | pass  | FAIL  | -
+run   | ok    | FAIL  | ERROR
+"
+        );
+    }
+
+    #[test]
+    fn boards_sharing_ports_run_one_after_another() {
+        let boards: Vec<RunnerConfig> = [
+            "probe-serial = \"A\"",
+            // same default RTT port as the first board
+            "probe-serial = \"B\"",
+            "probe-serial = \"C\"\nrtt-port = \"auto\"",
+            "rtt-port = \"auto\"\n[openocd-server]",
+            "rtt-port = \"auto\"\n[openocd-server]\ngdb-port = 3334\ntcl-port = 6667",
+        ]
+        .iter()
+        .map(|cfg| toml::from_str(cfg).unwrap())
+        .collect();
+        let board_ports: Vec<Vec<u16>> = boards.iter().map(|cfg| host_ports(cfg, false)).collect();
+
+        assert_eq!(
+            board_ports[2],
+            Vec::<u16>::new(),
+            "Auto RTT port is shared."
+        );
+        assert_eq!(
+            parallel_groups(&board_ports),
+            vec![vec![0, 1], vec![2], vec![3], vec![4]]
+        );
+    }
+}
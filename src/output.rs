@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use covcon::cfg::DataFormat;
+use mantra_schema::coverage::CoverageSchema;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    cfg::ResolvedConfig,
+    coverage::{self, TestFailure},
+    defmt::{DefmtLogs, ReadEnd},
+    junit, RunnerError,
+};
+
+/// Settings to store the results of one run.
+#[derive(Debug, Clone)]
+pub struct OutputSettings {
+    /// Directory to store logs and test results in.
+    pub output_dir: PathBuf,
+    /// Path of the executed binary relative to the workspace root.
+    pub rel_binary: PathBuf,
+    /// Optional name for the test run.
+    ///
+    /// Default: Relative path of the executed binary.
+    pub run_name: Option<String>,
+    /// Path to look for custom JSON data that is linked with the test run.
+    pub data_filepath: Option<PathBuf>,
+    /// `true`: Writes a JUnit XML report next to the coverage file.
+    pub junit: bool,
+    /// `true`: Registers the coverage file to be combined by the `collect` command.
+    pub register: bool,
+}
+
+/// Conditions of the run that are not visible in the received frames.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RunStatus {
+    /// `true`: The target stopped in the HardFault handler.
+    pub hard_fault: bool,
+    /// `true`: The run was interrupted by SIGINT or SIGTERM => results are partial.
+    pub interrupted: bool,
+    /// `true`: The run did not finish within the execution timeout => results are partial.
+    pub execution_timeout: bool,
+}
+
+pub async fn create_output_dir(output_dir: &Path) -> Result<(), RunnerError> {
+    if !output_dir.exists() {
+        tokio::fs::create_dir_all(output_dir).await.map_err(|err| {
+            RunnerError::Setup(format!(
+                "Could not create directory '{}'. Cause: {}",
+                output_dir.display(),
+                err
+            ))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Returns the default output directory `<binary filepath>_runner` of the binary.
+pub fn default_output_dir(binary: &Path) -> PathBuf {
+    let mut dir = binary.to_path_buf();
+    dir.set_file_name(format!(
+        "{}_runner",
+        binary
+            .file_name()
+            .expect("Binary name must be a valid filename.")
+            .to_string_lossy()
+    ));
+    dir
+}
+
+/// Returns the path of the binary relative to the workspace root,
+/// or the given path if the binary is outside of the workspace.
+pub fn relative_binary_path(binary: &Path) -> PathBuf {
+    binary
+        .strip_prefix(
+            crate::path::get_cargo_root().unwrap_or(std::env::current_dir().unwrap_or_default()),
+        )
+        .map(|p| p.to_path_buf())
+        .unwrap_or(binary.to_path_buf())
+}
+
+/// Creates the coverage and JUnit files of the run from the frames the given sinks received.
+///
+/// Failure reasons and messages of failed tests are stored in the `failures` entry of the test run data.
+/// The test that was running when the target stopped in the HardFault handler, or when the run got interrupted,
+/// is marked as failed with reason "HardFault" or "interrupted".
+/// Interrupted runs are marked with `"interrupted": true` in the test run data.
+///
+/// Returns the names of all failed tests.
+pub async fn store_results(
+    main_cfg: &ResolvedConfig,
+    settings: &OutputSettings,
+    defmt_logs: DefmtLogs,
+    status: RunStatus,
+) -> Result<Vec<String>, RunnerError> {
+    let output_dir = &settings.output_dir;
+    let rel_binary_str = settings.rel_binary.display().to_string();
+    let log_file = &defmt_logs.sinks.log_file;
+    let mut coverage_builder = defmt_logs.sinks.coverage;
+
+    println!("------------------ Output ------------------");
+
+    let mut failed_tests = Vec::new();
+
+    if log_file.nr_frames() == 0 {
+        println!("No logs received.");
+    } else {
+        println!("Logs written to '{}'.", log_file.filepath().display());
+
+        let run_name = settings.run_name.clone().unwrap_or(rel_binary_str.clone());
+
+        let data_path = settings
+            .data_filepath
+            .clone()
+            .or(main_cfg.runner_cfg.data_filepath.clone())
+            .unwrap_or(main_cfg.embedded_dir.join("test_run_data.json"));
+
+        let mut data = if data_path.exists() {
+            let data_content = tokio::fs::read_to_string(&data_path).await.map_err(|err| {
+                RunnerError::Setup(format!(
+                    "Could not read custom test run data '{}'. Cause: {}",
+                    data_path.display(),
+                    err
+                ))
+            })?;
+
+            let mut data: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(&data_content).map_err(|err| {
+                    RunnerError::Setup(format!(
+                        "Could not deserialize metadata '{}'. Cause: {}",
+                        data_path.display(),
+                        err
+                    ))
+                })?;
+
+            data.insert(
+                "binary".to_string(),
+                serde_json::Value::String(rel_binary_str),
+            );
+
+            serde_json::Value::Object(data)
+        } else {
+            json!({
+                "binary": rel_binary_str
+            })
+        };
+
+        if let Some(test_name) = coverage_builder.unfinished_test().map(str::to_string) {
+            let reason = if defmt_logs.end == ReadEnd::TestTimeout {
+                log::error!("Test '{test_name}' did not finish within the test timeout.");
+                Some("timeout")
+            } else if status.hard_fault {
+                log::error!("Test '{test_name}' caused a HardFault.");
+                Some("HardFault")
+            } else if status.execution_timeout {
+                log::error!("Test '{test_name}' did not finish within the execution timeout.");
+                Some("execution timeout")
+            } else if status.interrupted {
+                log::error!("Test '{test_name}' was interrupted.");
+                Some("interrupted")
+            } else {
+                None
+            };
+
+            if let Some(reason) = reason {
+                coverage_builder.set_failure_reason(&test_name, reason.to_string());
+            }
+        }
+
+        if let Some(extern_cov) = &main_cfg.runner_cfg.extern_coverage {
+            match (tokio::fs::read_to_string(&extern_cov.filepath).await, covcon::cfg::DataFormat::try_from(extern_cov.filepath.extension())) {
+                (Ok(content), Ok(DataFormat::Xml)) => {
+                    let cov_cfg = covcon::cfg::ConversionConfig {
+                        in_fmt: extern_cov.format,
+                        in_content: content,
+                        in_data_fmt: DataFormat::Xml,
+                        out_fmt: covcon::format::CoverageFormat::CoberturaV4,
+                        out_data_fmt: DataFormat::Json,
+                    };
+
+                    match covcon::convert::convert_to_json(&cov_cfg) {
+                        Ok(json_cov) => {
+                            let meta_map = data.as_object_mut().expect("Meta is created as object above.");
+                            meta_map.insert("coverage".to_string(), json_cov);
+                        },
+                        Err(err) => log::error!("Failed extracting external coverage data. External coverage will be ignored. Cause: {err}"),
+                    }
+                }
+                (Err(err), _) => log::error!("Failed to read external coverage file. External coverage will be ignored. Cause: {err}"),
+                (_, _) => log::error!("Coverage file must be XML. External coverage will be ignored."),
+            }
+        }
+
+        if status.interrupted {
+            println!("Run was interrupted. Results only contain the tests executed so far.");
+
+            let meta_map = data
+                .as_object_mut()
+                .expect("Meta is created as object above.");
+            meta_map.insert("interrupted".to_string(), serde_json::Value::Bool(true));
+        }
+
+        let mut failures = coverage_builder.failures().clone();
+        let mut coverage = coverage_builder
+            .build(run_name, Some(data))
+            .map_err(RunnerError::Coverage)?;
+
+        failed_tests = coverage::failed_tests(&coverage);
+        failures.retain(|test_name, _| failed_tests.contains(test_name));
+
+        if !failures.is_empty() {
+            print_failures(&failed_tests, &failures);
+
+            if let Some(serde_json::Value::Object(data)) = coverage
+                .test_runs
+                .first_mut()
+                .and_then(|test_run| test_run.data.as_mut())
+            {
+                data.insert(
+                    "failures".to_string(),
+                    serde_json::to_value(&failures).expect("Test failures are valid JSON."),
+                );
+            }
+        }
+
+        // If no tests were found, execution most likely `cargo run` or `cargo bench` => no test coverage
+        if coverage
+            .test_runs
+            .iter()
+            .any(|test_run| test_run.nr_of_tests > 0)
+        {
+            write_coverage(output_dir, &coverage, settings.junit, settings.register).await?;
+        }
+    }
+
+    Ok(failed_tests)
+}
+
+/// Writes the coverage file, and the JUnit report if enabled, to the output directory.
+///
+/// If `register` is set, the coverage file is registered to be combined by the `collect` command.
+pub async fn write_coverage(
+    output_dir: &Path,
+    coverage: &CoverageSchema,
+    junit: bool,
+    register: bool,
+) -> Result<(), RunnerError> {
+    let coverage_file = output_dir.join("coverage.json");
+    tokio::fs::write(
+        &coverage_file,
+        serde_json::to_string(coverage).expect("Coverage schema is valid JSON."),
+    )
+    .await
+    .map_err(|err| {
+        RunnerError::Setup(format!(
+            "Could not write to file '{}'. Cause: {}",
+            coverage_file.display(),
+            err
+        ))
+    })?;
+
+    println!("Coverage written to '{}'.", coverage_file.display());
+
+    if junit {
+        let junit_file = output_dir.join("junit.xml");
+        tokio::fs::write(&junit_file, junit::junit_from_coverage(coverage))
+            .await
+            .map_err(|err| {
+                RunnerError::Setup(format!(
+                    "Could not write to file '{}'. Cause: {}",
+                    junit_file.display(),
+                    err
+                ))
+            })?;
+
+        println!("JUnit report written to '{}'.", junit_file.display());
+    }
+
+    if !register {
+        return Ok(());
+    }
+
+    let coverages_filepath = coverage::coverages_filepath();
+
+    if !coverages_filepath.exists() {
+        let _w = tokio::fs::write(coverages_filepath, coverage_file.display().to_string()).await;
+    } else {
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .read(true)
+            .open(coverages_filepath)
+            .await
+            .expect("Coverages file exists.");
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .await
+            .expect("Reading coverages");
+
+        let mut exists = false;
+        for line in content.lines() {
+            if line == coverage_file.display().to_string() {
+                exists = true;
+                break;
+            }
+        }
+
+        if !exists {
+            let _w = file.write_all("\n".as_bytes()).await;
+            let _w = file
+                .write_all(coverage_file.display().to_string().as_bytes())
+                .await;
+        }
+
+        let _f = file.flush().await;
+    }
+
+    Ok(())
+}
+
+fn print_failures(failed_tests: &[String], failures: &HashMap<String, TestFailure>) {
+    println!("Failed tests:");
+
+    for test_name in failed_tests {
+        match failures.get(test_name) {
+            Some(failure) => {
+                println!(
+                    "  {test_name}: {}",
+                    failure.reason.as_deref().unwrap_or("error logged")
+                );
+                for message in failure
+                    .messages
+                    .iter()
+                    .filter(|msg| Some(*msg) != failure.reason.as_ref())
+                {
+                    println!("    {message}");
+                }
+            }
+            None => println!("  {test_name}"),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Child processes started for a run (e.g. GDB, GDB servers, QEMU).
+//!
+//! Children run in their own process group, so Ctrl-C in the terminal does not reach them.
+//! They are registered, so they may still be killed if the runner exits without running destructors.
+
+use std::sync::Mutex;
+
+use tokio::process::{Child, Command};
+
+/// PIDs of the running children, which are also the IDs of their process groups.
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Removes the child from the registry once dropped.
+///
+/// Must be stored next to the [`Child`], so the child is no longer killed after it was reaped.
+#[derive(Debug)]
+pub struct Registration {
+    pid: Option<u32>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(pid) = self.pid {
+            registry().retain(|child| *child != pid);
+        }
+    }
+}
+
+/// Spawns the command in its own process group, and registers it to be killed by [`kill_all()`].
+pub fn spawn(cmd: &mut Command) -> std::io::Result<(Child, Registration)> {
+    // Ctrl-C in the terminal must not reach GDB and the GDB server directly,
+    // because the runner stops the target and shuts both down itself.
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    let child = cmd.spawn()?;
+    let pid = child.id();
+    if let Some(pid) = pid {
+        registry().push(pid);
+    }
+
+    Ok((child, Registration { pid }))
+}
+
+/// Kills all registered children, including the processes they started (e.g. OpenOCD started by GDB).
+///
+/// Used before the process exits immediately, because destructors that would kill the children do not run.
+pub fn kill_all() {
+    let children = std::mem::take(&mut *registry());
+    kill_groups(&children);
+}
+
+fn registry() -> std::sync::MutexGuard<'static, Vec<u32>> {
+    // registry stays valid even if a thread panicked while holding the lock
+    CHILDREN.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Kills the process groups of the given children.
+fn kill_groups(children: &[u32]) {
+    for pid in children {
+        #[cfg(unix)]
+        let result = std::process::Command::new("kill")
+            .args(["-KILL", "--", &format!("-{pid}")])
+            .status();
+        #[cfg(windows)]
+        let result = std::process::Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .status();
+
+        if let Err(err) = result {
+            log::warn!("Could not kill child process with PID {pid}. Cause: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Stdio;
+
+    use tokio::io::AsyncBufReadExt;
+
+    /// Returns `true` if the process with the given PID exited, even if it was not reaped yet.
+    #[cfg(target_os = "linux")]
+    fn exited(pid: u32) -> bool {
+        match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+            // state follows the executable name in parentheses
+            Ok(stat) => stat
+                .rsplit_once(')')
+                .is_some_and(|(_, rest)| rest.trim_start().starts_with(['Z', 'X'])),
+            Err(_) => true,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn killed_children_leave_no_process_behind() {
+        // shell stands in for GDB, and its background job for the GDB server started by GDB
+        let (mut child, registration) = super::spawn(
+            tokio::process::Command::new("sh")
+                .args(["-c", "sleep 100 & echo $!; wait"])
+                .stdout(Stdio::piped()),
+        )
+        .unwrap();
+        let pid = child.id().unwrap();
+        let mut lines = tokio::io::BufReader::new(child.stdout.take().unwrap()).lines();
+        let server_pid: u32 = lines.next_line().await.unwrap().unwrap().parse().unwrap();
+
+        assert!(super::registry().contains(&pid), "Child not registered.");
+
+        // only this child, because other tests register their children in the same registry
+        super::kill_groups(&[pid]);
+        child.wait().await.unwrap();
+
+        // SIGKILL is delivered asynchronously to the remaining process
+        for _ in 0..50 {
+            if exited(server_pid) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        assert!(exited(pid), "Child not killed.");
+        assert!(
+            exited(server_pid),
+            "Process started by the child not killed."
+        );
+
+        drop(registration);
+        assert!(
+            !super::registry().contains(&pid),
+            "Reaped child still registered."
+        );
+    }
+}
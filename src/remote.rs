@@ -0,0 +1,674 @@
+//! Runs binaries on the probes of another host.
+//!
+//! The agent listens on TCP, and runs every binary it receives like `embedded-runner run` on its host.
+//! Messages are JSON lines:
+//!
+//! 1. The client sends a [`RunRequest`], followed by the raw bytes of the binary.
+//! 2. The agent streams [`AgentMessage::Frame`] for every decoded frame,
+//!    and ends with [`AgentMessage::Finished`] that contains the coverage of the run.
+//! 3. The client may send [`ClientMessage::Interrupt`] at any time to stop the run.
+//!    Closing the connection also stops the run.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use clap::Parser;
+use defmt_json_schema::v1::JsonFrame;
+use mantra_schema::coverage::CoverageSchema;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cfg::{AgentCmdConfig, ResolvedConfig, RunCmdConfig},
+    output,
+    sink::{ConsoleSink, FrameSink, LogFileSink, SinkError},
+    RunControl, RunnerError, EXIT_CODE_INTERRUPTED, EXIT_CODE_TEST_FAILURE,
+};
+
+/// Binary and settings of one run sent to the agent.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RunRequest {
+    /// Filename of the binary.
+    pub binary_name: String,
+    /// Number of bytes of the binary that follow the request.
+    pub binary_len: u64,
+    pub run_name: Option<String>,
+    /// Board in the runner configuration of the agent.
+    pub board: Option<String>,
+    pub probe_serial: Option<String>,
+    pub setup_timeout: Option<u64>,
+    pub execution_timeout: Option<u64>,
+    pub test_timeout: Option<u64>,
+    pub completion_pattern: Option<String>,
+}
+
+/// Messages sent by the agent.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum AgentMessage {
+    Frame(JsonFrame),
+    Finished {
+        coverage: Option<CoverageSchema>,
+        /// Exit code `embedded-runner run` would have returned on the agent host.
+        exit_code: i32,
+        error: Option<String>,
+        failed_tests: Vec<String>,
+    },
+}
+
+/// Messages sent by the client while the run is ongoing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ClientMessage {
+    Interrupt,
+}
+
+/// Maximum size of binaries the agent accepts.
+const MAX_BINARY_LEN: u64 = 64 * 1024 * 1024;
+/// Maximum length of messages sent by the client.
+const MAX_CLIENT_MESSAGE_LEN: u64 = 64 * 1024;
+/// Maximum length of messages sent by the agent.
+///
+/// The coverage in the finished message is the largest message, and contains at most 1000 frames per failed test.
+const MAX_AGENT_MESSAGE_LEN: u64 = 16 * 1024 * 1024;
+/// Maximum duration the client may block sending one frame, before frames are no longer sent.
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Counts runs of the agent to get unique directories for runs started at the same time.
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Listens for runs on the address set in the agent configuration.
+pub async fn agent(main_cfg: ResolvedConfig, agent_cfg: AgentCmdConfig) -> Result<(), RunnerError> {
+    let listener = TcpListener::bind(&agent_cfg.listen).await.map_err(|err| {
+        RunnerError::Setup(format!(
+            "Could not listen on '{}'. Cause: {err}",
+            agent_cfg.listen
+        ))
+    })?;
+    println!("Agent listening on '{}'.", agent_cfg.listen);
+
+    serve(listener, main_cfg).await
+}
+
+/// Runs every binary received on the given listener until Ctrl-C is pressed.
+///
+/// Runs on the same probe wait for each other, because every run locks its probe.
+pub async fn serve(listener: TcpListener, main_cfg: ResolvedConfig) -> Result<(), RunnerError> {
+    let main_cfg = Arc::new(main_cfg);
+    let mut runs = JoinSet::new();
+
+    loop {
+        while runs.try_join_next().is_some() {}
+
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    log::error!("Could not accept connection. Cause: {err}");
+                    continue;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        };
+
+        let main_cfg = main_cfg.clone();
+        runs.spawn(async move {
+            log::info!("Run requested by '{peer}'.");
+
+            if let Err(err) = handle_run(&main_cfg, stream).await {
+                log::error!("Run requested by '{peer}' failed. Cause: {err}");
+            }
+        });
+    }
+
+    // ongoing runs are interrupted by the same Ctrl-C
+    println!("Agent stopped. Waiting for ongoing runs.");
+    while runs.join_next().await.is_some() {}
+
+    Ok(())
+}
+
+/// Receives one binary, runs it, and sends back its frames and results.
+///
+/// The binary and the results are removed once they were sent back.
+async fn handle_run(main_cfg: &ResolvedConfig, stream: TcpStream) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let request: RunRequest = read_message(&mut reader, MAX_CLIENT_MESSAGE_LEN)
+        .await?
+        .ok_or(std::io::ErrorKind::UnexpectedEof)?;
+
+    if request.binary_len > MAX_BINARY_LEN {
+        let err = RunnerError::Setup(format!(
+            "Binary has {} bytes, but the agent accepts at most {MAX_BINARY_LEN} bytes.",
+            request.binary_len
+        ));
+        let finished = AgentMessage::Finished {
+            coverage: None,
+            exit_code: err.exit_code(),
+            error: Some(err.to_string()),
+            failed_tests: Vec::new(),
+        };
+        write_message(&mut writer, &finished).await?;
+
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            err.to_string(),
+        ));
+    }
+
+    let mut binary_content = vec![0; request.binary_len as usize];
+    reader.read_exact(&mut binary_content).await?;
+
+    let run_dir = main_cfg.embedded_dir.join("agent").join(format!(
+        "{}-{}",
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::create_dir_all(&run_dir).await?;
+
+    let result = run_in_dir(main_cfg, &run_dir, request, binary_content, reader, writer).await;
+
+    if let Err(err) = tokio::fs::remove_dir_all(&run_dir).await {
+        log::warn!(
+            "Could not remove run directory '{}'. Cause: {err}",
+            run_dir.display()
+        );
+    }
+
+    result
+}
+
+/// Runs the received binary inside the given run directory, and streams frames and results to the client.
+async fn run_in_dir(
+    main_cfg: &ResolvedConfig,
+    run_dir: &Path,
+    request: RunRequest,
+    binary_content: Vec<u8>,
+    mut reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+) -> std::io::Result<()> {
+    // only the filename is used, so clients cannot write outside the run directory
+    let binary_name = PathBuf::from(&request.binary_name)
+        .file_name()
+        .map(PathBuf::from)
+        .unwrap_or(PathBuf::from("binary"));
+    let binary = run_dir.join(binary_name);
+    tokio::fs::write(&binary, binary_content).await?;
+
+    let mut run_cfg = RunCmdConfig::parse_from([PathBuf::from("run"), binary.clone()]);
+    run_cfg.run_name = request.run_name;
+    run_cfg.board = request.board;
+    run_cfg.probe_serial = request.probe_serial;
+    run_cfg.setup_timeout = request.setup_timeout;
+    run_cfg.execution_timeout = request.execution_timeout;
+    run_cfg.test_timeout = request.test_timeout;
+    run_cfg.completion_pattern = request.completion_pattern;
+
+    let (frame_sender, mut frames) = tokio::sync::mpsc::unbounded_channel();
+    let control = RunControl {
+        frames: Some(frame_sender),
+        interrupt: CancellationToken::new(),
+        // run directory is removed once the results were sent
+        skip_collect: true,
+    };
+
+    let interrupt = control.interrupt.clone();
+    let client = tokio::spawn(async move {
+        // any message, or a closed connection stops the run
+        let _ = read_message::<ClientMessage, _>(&mut reader, MAX_CLIENT_MESSAGE_LEN).await;
+        interrupt.cancel();
+    });
+
+    // forwarded in its own task, so a slow client does not stall the run
+    let forwarder = tokio::spawn(async move {
+        while let Some(frame) = frames.recv().await {
+            let written = tokio::time::timeout(
+                CLIENT_WRITE_TIMEOUT,
+                write_message(&mut writer, &AgentMessage::Frame(frame)),
+            )
+            .await
+            .unwrap_or(Err(std::io::ErrorKind::TimedOut.into()));
+
+            // receiver is dropped on return, so the run stops queueing frames
+            if let Err(err) = written {
+                return (writer, Err(err));
+            }
+        }
+
+        (writer, Ok(()))
+    });
+
+    // run must not be dropped if the client is gone, because the backend must be torn down
+    let result = crate::run_binary(main_cfg, run_cfg, control).await;
+    client.abort();
+
+    // sender was dropped with the run => forwarder ends once all frames are sent
+    let (mut writer, forward_result) = forwarder.await.map_err(std::io::Error::other)?;
+    forward_result?;
+
+    let coverage =
+        tokio::fs::read_to_string(output::default_output_dir(&binary).join("coverage.json"))
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok());
+    let finished = match result {
+        Ok(()) => AgentMessage::Finished {
+            coverage,
+            exit_code: 0,
+            error: None,
+            failed_tests: Vec::new(),
+        },
+        Err(err) => AgentMessage::Finished {
+            coverage,
+            exit_code: err.exit_code(),
+            error: Some(err.to_string()),
+            failed_tests: match err {
+                RunnerError::TestsFailed(tests) => tests,
+                _ => Vec::new(),
+            },
+        },
+    };
+
+    write_message(&mut writer, &finished).await
+}
+
+/// Runs the binary on the agent at the given address, and stores the results like a local run.
+pub async fn run(
+    main_cfg: &ResolvedConfig,
+    run_cfg: RunCmdConfig,
+    agent: &str,
+) -> Result<(), RunnerError> {
+    let binary_content = tokio::fs::read(&run_cfg.binary).await.map_err(|err| {
+        RunnerError::Setup(format!(
+            "Could not read binary '{}'. Cause: {err}",
+            run_cfg.binary.display()
+        ))
+    })?;
+    let connection_err =
+        |err: std::io::Error| RunnerError::Connection(format!("Agent '{agent}': {err}"));
+
+    println!("-------------------- Remote Run on '{agent}' --------------------");
+
+    let stream = TcpStream::connect(agent).await.map_err(connection_err)?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let request = RunRequest {
+        binary_name: run_cfg
+            .binary
+            .file_name()
+            .expect("Binary name must be a valid filename.")
+            .to_string_lossy()
+            .into_owned(),
+        binary_len: binary_content.len() as u64,
+        // name of a local run, so results of remote and local runs may be compared
+        run_name: Some(
+            run_cfg.run_name.clone().unwrap_or(
+                output::relative_binary_path(&run_cfg.binary)
+                    .display()
+                    .to_string(),
+            ),
+        ),
+        board: run_cfg.board.clone(),
+        probe_serial: run_cfg.probe_serial.clone(),
+        setup_timeout: run_cfg.setup_timeout,
+        execution_timeout: run_cfg.execution_timeout,
+        test_timeout: run_cfg.test_timeout,
+        completion_pattern: run_cfg.completion_pattern.clone(),
+    };
+    write_message(&mut writer, &request)
+        .await
+        .map_err(connection_err)?;
+    writer
+        .write_all(&binary_content)
+        .await
+        .map_err(connection_err)?;
+
+    let output_dir = run_cfg
+        .output_dir
+        .clone()
+        .unwrap_or(output::default_output_dir(&run_cfg.binary));
+    output::create_output_dir(&output_dir).await?;
+    let log_file = LogFileSink::create(output_dir.join("defmt.log"))
+        .await
+        .map_err(|err| RunnerError::Defmt(err.to_string()))?;
+    let (frame_sender, frame_receiver) = std::sync::mpsc::channel();
+    let log_writer = tokio::task::spawn_blocking(move || write_frames(log_file, frame_receiver));
+
+    let interrupt = crate::interrupt_token(&CancellationToken::new());
+    let mut interrupted = false;
+
+    let finished = loop {
+        let message = tokio::select! {
+            message = read_message::<AgentMessage, _>(&mut reader, MAX_AGENT_MESSAGE_LEN) => message.map_err(connection_err)?,
+            _ = interrupt.cancelled(), if !interrupted => {
+                interrupted = true;
+                write_message(&mut writer, &ClientMessage::Interrupt)
+                    .await
+                    .map_err(connection_err)?;
+                continue;
+            }
+        };
+
+        match message {
+            Some(AgentMessage::Frame(frame)) => {
+                // a failed writer is reported once it is joined
+                let _ = frame_sender.send(frame);
+            }
+            Some(finished @ AgentMessage::Finished { .. }) => break finished,
+            None => {
+                return Err(RunnerError::Connection(format!(
+                    "Agent '{agent}' closed the connection before the run finished."
+                )))
+            }
+        }
+    };
+    drop(frame_sender);
+    let log_file = log_writer
+        .await
+        .map_err(|_| RunnerError::Defmt("Failed waiting for defmt logs.".to_string()))?
+        .map_err(|err| RunnerError::Defmt(err.to_string()))?;
+
+    let AgentMessage::Finished {
+        coverage,
+        exit_code,
+        error,
+        failed_tests,
+    } = finished
+    else {
+        unreachable!("Loop only ends on the finished message.");
+    };
+
+    println!("------------------ Output ------------------");
+    if log_file.nr_frames() > 0 {
+        println!("Logs written to '{}'.", log_file.filepath().display());
+    }
+    if let Some(coverage) = coverage {
+        output::write_coverage(
+            &output_dir,
+            &coverage,
+            run_cfg.junit.unwrap_or(main_cfg.runner_cfg.junit),
+            true,
+        )
+        .await?;
+    }
+
+    match exit_code {
+        0 => Ok(()),
+        EXIT_CODE_TEST_FAILURE => Err(RunnerError::TestsFailed(failed_tests)),
+        EXIT_CODE_INTERRUPTED => Err(RunnerError::Interrupted),
+        _ => Err(RunnerError::Remote(error.unwrap_or_default(), exit_code)),
+    }
+}
+
+/// Writes frames received from the agent to the log file and the console.
+///
+/// Runs on a blocking thread, because the sinks use blocking IO.
+fn write_frames(
+    mut log_file: LogFileSink,
+    frames: std::sync::mpsc::Receiver<JsonFrame>,
+) -> Result<LogFileSink, SinkError> {
+    let mut console = ConsoleSink;
+
+    for frame in frames {
+        log_file.push(&frame)?;
+        console.push(&frame)?;
+    }
+    log_file.finish()?;
+
+    Ok(log_file)
+}
+
+/// Reads one JSON line of at most `max_len` bytes, or returns `None` if the connection was closed.
+async fn read_message<T, R>(reader: &mut BufReader<R>, max_len: u64) -> std::io::Result<Option<T>>
+where
+    T: serde::de::DeserializeOwned,
+    R: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    let read = reader.take(max_len).read_until(b'\n', &mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read as u64 == max_len && !line.ends_with(b"\n") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Message exceeds {max_len} bytes."),
+        ));
+    }
+
+    serde_json::from_slice(&line)
+        .map(Some)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+async fn write_message<T, W>(writer: &mut W, message: &T) -> std::io::Result<()>
+where
+    T: serde::Serialize,
+    W: AsyncWrite + Unpin,
+{
+    let mut line = serde_json::to_vec(message).expect("Messages are valid JSON.");
+    line.push(b'\n');
+    writer.write_all(&line).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+    use defmt_json_schema::v1::{JsonFrame, Location};
+    use mantra_schema::coverage::{CoverageSchema, Test, TestRun, TestState};
+    use tokio::{
+        io::{AsyncReadExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{
+        cfg::{ResolvedConfig, RunCmdConfig},
+        RunnerError, EXIT_CODE_CONFIGURATION, EXIT_CODE_TEST_FAILURE,
+    };
+
+    use super::{read_message, write_message, AgentMessage, RunRequest};
+
+    const BINARY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_binaries/emb-runner-test");
+
+    fn main_cfg(name: &str, runner_cfg: &str) -> ResolvedConfig {
+        let embedded_dir = std::env::temp_dir().join(format!("emb-runner-{name}"));
+        std::fs::create_dir_all(&embedded_dir).unwrap();
+
+        ResolvedConfig {
+            runner_cfg: toml::from_str(runner_cfg).unwrap(),
+            verbose: false,
+            workspace_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+            embedded_dir,
+        }
+    }
+
+    #[tokio::test]
+    async fn agent_reports_run_error() {
+        // QEMU backend without `[qemu]` section fails without hardware
+        let main_cfg = main_cfg("agent-run-error", "backend = \"qemu\"");
+        // directories of earlier test runs
+        let _ = std::fs::remove_dir_all(main_cfg.embedded_dir.join("agent"));
+        let output_dir = main_cfg.embedded_dir.join("client");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent = listener.local_addr().unwrap().to_string();
+        let agent_cfg = main_cfg.clone();
+        let agent_task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            super::handle_run(&agent_cfg, stream).await
+        });
+
+        let run_cfg =
+            RunCmdConfig::parse_from(["run", "--output-dir", output_dir.to_str().unwrap(), BINARY]);
+        let result = super::run(&main_cfg, run_cfg, &agent).await;
+        agent_task.await.unwrap().unwrap();
+
+        assert_eq!(
+            std::fs::read_dir(main_cfg.embedded_dir.join("agent"))
+                .unwrap()
+                .count(),
+            0,
+            "Run directory not removed."
+        );
+
+        match result {
+            Err(RunnerError::Remote(msg, exit_code)) => {
+                assert_eq!(exit_code, EXIT_CODE_CONFIGURATION);
+                assert!(msg.contains("[qemu]"), "Remote error not forwarded.");
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn agent_rejects_large_binary() {
+        let main_cfg = main_cfg("agent-large-binary", "");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent = listener.local_addr().unwrap();
+        let agent_task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            super::handle_run(&main_cfg, stream).await
+        });
+
+        let stream = TcpStream::connect(agent).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let request = RunRequest {
+            binary_name: "huge.elf".to_string(),
+            binary_len: super::MAX_BINARY_LEN + 1,
+            run_name: None,
+            board: None,
+            probe_serial: None,
+            setup_timeout: None,
+            execution_timeout: None,
+            test_timeout: None,
+            completion_pattern: None,
+        };
+        write_message(&mut writer, &request).await.unwrap();
+
+        let finished = read_message(&mut reader, super::MAX_AGENT_MESSAGE_LEN)
+            .await
+            .unwrap();
+        assert!(
+            matches!(finished, Some(AgentMessage::Finished { error: Some(msg), .. }) if msg.contains("at most")),
+            "Large binary not rejected."
+        );
+        assert!(agent_task.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_message_is_rejected() {
+        let mut reader = BufReader::new(&[b'x'; 100][..]);
+
+        let message = read_message::<RunRequest, _>(&mut reader, 10).await;
+
+        assert_eq!(message.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn client_stores_remote_results() {
+        let main_cfg = main_cfg("client-results", "");
+        let output_dir = main_cfg.embedded_dir.join("client");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent = listener.local_addr().unwrap().to_string();
+
+        let fake_agent = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+
+            let request: RunRequest = read_message(&mut reader, super::MAX_CLIENT_MESSAGE_LEN)
+                .await
+                .unwrap()
+                .unwrap();
+            let mut binary = vec![0; request.binary_len as usize];
+            reader.read_exact(&mut binary).await.unwrap();
+            assert_eq!(request.binary_name, "emb-runner-test");
+            assert_eq!(binary, std::fs::read(BINARY).unwrap(), "Binary not sent.");
+
+            let frame = JsonFrame {
+                data: "(1/1) running `adc`...".to_string(),
+                host_timestamp: 0,
+                level: None,
+                location: Location {
+                    file: None,
+                    line: None,
+                    module_path: None,
+                },
+                target_timestamp: String::new(),
+            };
+            let coverage = CoverageSchema {
+                version: None,
+                test_runs: vec![TestRun {
+                    name: request.run_name.unwrap(),
+                    date: time::OffsetDateTime::UNIX_EPOCH,
+                    nr_of_tests: 1,
+                    data: None,
+                    logs: None,
+                    tests: vec![Test {
+                        name: "adc".to_string(),
+                        filepath: PathBuf::from("tests/emb.rs"),
+                        line: 1,
+                        state: TestState::Failed,
+                        covered_files: Vec::new(),
+                    }],
+                }],
+            };
+
+            write_message(&mut writer, &AgentMessage::Frame(frame))
+                .await
+                .unwrap();
+            write_message(
+                &mut writer,
+                &AgentMessage::Finished {
+                    coverage: Some(coverage),
+                    exit_code: EXIT_CODE_TEST_FAILURE,
+                    error: Some("1 test(s) failed: adc".to_string()),
+                    failed_tests: vec!["adc".to_string()],
+                },
+            )
+            .await
+            .unwrap();
+        });
+
+        let run_cfg =
+            RunCmdConfig::parse_from(["run", "--output-dir", output_dir.to_str().unwrap(), BINARY]);
+        let result = super::run(&main_cfg, run_cfg, &agent).await;
+        fake_agent.await.unwrap();
+
+        assert!(
+            matches!(result, Err(RunnerError::TestsFailed(tests)) if tests == ["adc"]),
+            "Failed tests not forwarded."
+        );
+        assert!(
+            std::fs::read_to_string(output_dir.join("defmt.log"))
+                .unwrap()
+                .contains("running `adc`"),
+            "Frames not stored."
+        );
+        assert!(
+            output_dir.join("coverage.json").exists(),
+            "Coverage not stored."
+        );
+    }
+}
@@ -0,0 +1,716 @@
+//! Simulated target to test the runner without hardware.
+//!
+//! The target pretends to be a GDB remote server that supports the `monitor rtt` commands of OpenOCD.
+//! Once the binary runs past `main`, a recorded defmt byte stream is replayed over the RTT server.
+//!
+//! Use the address of the server as `gdb-connection` in the runner configuration.
+//!
+//! see: https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+use std::{
+    collections::BTreeSet,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use object::{Object, ObjectSection, ObjectSymbol, SectionFlags};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SimError {
+    #[error("Could not read binary file. Cause: {}", .0)]
+    Read(std::io::Error),
+    #[error("Could not parse binary file. Cause: {}", .0)]
+    Parse(object::Error),
+}
+
+/// Number of registers in the `g` packet (r0-r12, sp, lr, pc, xpsr).
+const NR_REGISTERS: usize = 17;
+const SP: usize = 13;
+const PC: usize = 15;
+const XPSR: usize = 16;
+/// Register number of xPSR in the target description.
+const XPSR_REGNUM: usize = 25;
+/// Thumb bit of the xPSR register, which is always set on Cortex-M.
+const XPSR_THUMB: u32 = 1 << 24;
+/// Initial stack pointer of the simulated target.
+const INITIAL_SP: u32 = 0x2001_0000;
+
+/// Number of replayed bytes that are sent at once.
+const REPLAY_CHUNK_SIZE: usize = 64;
+/// Delay between two chunks of replayed bytes.
+const REPLAY_CHUNK_DELAY: Duration = Duration::from_millis(1);
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Target that runs a binary by replaying a recorded defmt byte stream.
+#[derive(Debug, Clone)]
+pub struct SimTarget {
+    entry: u32,
+    /// Address range of the `HardFault` handler.
+    hard_fault: Option<(u32, u32)>,
+    /// Allocated sections of the binary as `(address, data)`.
+    memory: Vec<(u32, Vec<u8>)>,
+    rtt_data: Vec<u8>,
+}
+
+impl SimTarget {
+    /// Creates a target for the given binary that replays the given RTT data once it runs past `main`.
+    ///
+    /// The RTT data may be recorded with the runner, which stores it as `rtt.bin` in the output directory.
+    pub fn new(binary: &Path, rtt_data: Vec<u8>) -> Result<Self, SimError> {
+        let data = std::fs::read(binary).map_err(SimError::Read)?;
+        let file = object::File::parse(&*data).map_err(SimError::Parse)?;
+
+        let hard_fault = file
+            .symbols()
+            .find(|symbol| symbol.name() == Ok("HardFault"))
+            .map(|symbol| {
+                let start = symbol.address() as u32 & !1;
+                (start, start + symbol.size() as u32)
+            });
+
+        let memory = file
+            .sections()
+            .filter(|section| {
+                matches!(section.flags(), SectionFlags::Elf { sh_flags } if sh_flags & u64::from(object::elf::SHF_ALLOC) != 0)
+            })
+            .filter_map(|section| {
+                let data = section.data().ok()?;
+                (!data.is_empty()).then(|| (section.address() as u32, data.to_vec()))
+            })
+            .collect();
+
+        Ok(Self {
+            entry: file.entry() as u32 & !1,
+            hard_fault,
+            memory,
+            rtt_data,
+        })
+    }
+
+    /// Starts the GDB remote server on a free local port.
+    ///
+    /// GDB may connect multiple times, but only one connection is served at a time.
+    /// Every connection starts with a freshly reset target.
+    pub async fn serve(self) -> std::io::Result<SimServer> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)).await?;
+        let addr = listener.local_addr()?;
+        let target = Arc::new(self);
+
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Err(err) = Session::new(target.clone()).serve(stream).await {
+                    log::debug!("Simulated target lost the GDB connection. Cause: {err}");
+                }
+            }
+        });
+
+        Ok(SimServer { addr, task })
+    }
+
+    fn read_memory(&self, address: u32, length: usize) -> Vec<u8> {
+        let mut bytes = vec![0; length];
+
+        for (start, data) in &self.memory {
+            for (offset, byte) in bytes.iter_mut().enumerate() {
+                let Some(index) = (address as usize + offset).checked_sub(*start as usize) else {
+                    continue;
+                };
+                if let Some(value) = data.get(index) {
+                    *byte = *value;
+                }
+            }
+        }
+
+        bytes
+    }
+
+    fn is_hard_fault(&self, address: u32) -> bool {
+        self.hard_fault
+            .is_some_and(|(start, end)| (start..end).contains(&address))
+    }
+}
+
+/// GDB remote server of a running simulated target.
+///
+/// The server is stopped once this handle is dropped.
+#[derive(Debug)]
+pub struct SimServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl SimServer {
+    pub fn gdb_port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Connection to set as `gdb-connection` in the runner configuration.
+    pub fn gdb_connection(&self) -> String {
+        format!("localhost:{}", self.gdb_port())
+    }
+}
+
+impl Drop for SimServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Execution state of the simulated target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// The binary did not reach `main` yet.
+    Reset,
+    /// The binary reached `main`, so continuing replays the RTT data.
+    Started,
+}
+
+/// State of the RTT server of the target.
+enum Rtt {
+    Stopped,
+    Listening(AcceptTask),
+    Connected(TcpStream),
+}
+
+/// Accepts the connection to the RTT server, and sends the greeting of the server.
+///
+/// Like OpenOCD, connections are accepted right away, even if the target is not running.
+struct AcceptTask(JoinHandle<std::io::Result<TcpStream>>);
+
+impl AcceptTask {
+    fn spawn(listener: TcpListener, greeting: Option<String>) -> Self {
+        Self(tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            if let Some(greeting) = greeting {
+                stream.write_all(greeting.as_bytes()).await?;
+            }
+            Ok(stream)
+        }))
+    }
+}
+
+impl Drop for AcceptTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Bytes replayed over RTT while the target is running.
+struct Replay {
+    stop: CancellationToken,
+    task: JoinHandle<(Rtt, usize)>,
+}
+
+/// Input received from GDB.
+enum Input {
+    Packet(Vec<u8>),
+    /// GDB requests to halt the running target.
+    Interrupt,
+}
+
+/// One GDB connection to the simulated target.
+struct Session {
+    target: Arc<SimTarget>,
+    registers: [u32; NR_REGISTERS],
+    breakpoints: BTreeSet<u32>,
+    stage: Stage,
+    rtt: Option<Rtt>,
+    /// Number of RTT bytes that were already replayed.
+    replayed: usize,
+}
+
+impl Session {
+    fn new(target: Arc<SimTarget>) -> Self {
+        let mut registers = [0; NR_REGISTERS];
+        registers[SP] = INITIAL_SP;
+        registers[PC] = target.entry;
+        registers[XPSR] = XPSR_THUMB;
+
+        Self {
+            target,
+            registers,
+            breakpoints: BTreeSet::new(),
+            stage: Stage::Reset,
+            rtt: Some(Rtt::Stopped),
+            replayed: 0,
+        }
+    }
+
+    async fn serve(self, stream: TcpStream) -> std::io::Result<()> {
+        let (reader, writer) = stream.into_split();
+
+        // packets are read in a separate task, because reading a packet is not cancel-safe
+        let (input_sender, inputs) = mpsc::unbounded_channel();
+        let reader_task = tokio::spawn(async move {
+            let mut reader = BufReader::new(reader);
+            while let Ok(Some(input)) = read_input(&mut reader).await {
+                if input_sender.send(input).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let result = self.handle_inputs(inputs, writer).await;
+        reader_task.abort();
+        result
+    }
+
+    async fn handle_inputs(
+        mut self,
+        mut inputs: mpsc::UnboundedReceiver<Input>,
+        mut writer: OwnedWriteHalf,
+    ) -> std::io::Result<()> {
+        let mut replay: Option<Replay> = None;
+
+        loop {
+            let input = tokio::select! {
+                input = inputs.recv() => input,
+                replayed = async { (&mut replay.as_mut().expect("Checked in condition.").task).await }, if replay.is_some() => {
+                    replay = None;
+                    self.finish_replay(replayed)?;
+
+                    // target stops once all recorded bytes are sent
+                    self.registers[PC] += 2;
+                    send_packet(&mut writer, &stop_reply(SIGTRAP)).await?;
+                    continue;
+                }
+            };
+
+            let packet = match input {
+                Some(Input::Packet(packet)) => packet,
+                Some(Input::Interrupt) => {
+                    if let Some(running) = replay.take() {
+                        running.stop.cancel();
+                        self.finish_replay(running.task.await)?;
+                        send_packet(&mut writer, &stop_reply(SIGINT)).await?;
+                    }
+                    continue;
+                }
+                None => return Ok(()),
+            };
+            writer.write_all(b"+").await?;
+
+            let command = String::from_utf8_lossy(&packet).into_owned();
+            log::trace!("Simulated target received '{command}'.");
+
+            let reply = match command.as_str() {
+                "c" | "s" | "vCont;c" | "vCont;s" if replay.is_some() => String::new(),
+                "c" | "vCont;c" => match self.resume() {
+                    Some(stop) => stop,
+                    None => {
+                        replay = Some(self.start_replay());
+                        continue;
+                    }
+                },
+                "s" | "vCont;s" => {
+                    self.registers[PC] += 2;
+                    stop_reply(SIGTRAP)
+                }
+                "k" => return Ok(()),
+                cmd if cmd.starts_with('D') => {
+                    send_packet(&mut writer, "OK").await?;
+                    return Ok(());
+                }
+                cmd => self.reply(cmd).await,
+            };
+
+            send_packet(&mut writer, &reply).await?;
+        }
+    }
+
+    /// Replies to commands that do not change the execution state of the target.
+    async fn reply(&mut self, cmd: &str) -> String {
+        if cmd.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+".to_string();
+        }
+        if let Some(request) = cmd.strip_prefix("qXfer:features:read:target.xml:") {
+            return xfer_chunk(TARGET_XML, request).unwrap_or("E01".to_string());
+        }
+        if let Some(hex_cmd) = cmd.strip_prefix("qRcmd,") {
+            return match hex_decode(hex_cmd).map(|cmd| String::from_utf8_lossy(&cmd).into_owned()) {
+                Some(monitor_cmd) => self.monitor(&monitor_cmd).await,
+                None => "E01".to_string(),
+            };
+        }
+
+        if !cmd.is_char_boundary(1) {
+            return String::new();
+        }
+
+        match cmd.split_at(1) {
+            ("g", "") => self.registers.iter().map(|value| hex_u32(*value)).collect(),
+            ("p", reg) => match register_index(reg) {
+                Some(reg) => hex_u32(self.registers[reg]),
+                None => "E01".to_string(),
+            },
+            ("P", assignment) => {
+                let Some((reg, value)) = assignment.split_once('=') else {
+                    return "E01".to_string();
+                };
+                match (register_index(reg), parse_hex_u32(value)) {
+                    (Some(reg), Some(value)) => {
+                        // value is sent in target byte order
+                        self.registers[reg] = value.swap_bytes();
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            ("m", range) => match parse_range(range) {
+                Some((address, length)) => hex_encode(&self.target.read_memory(address, length)),
+                None => "E01".to_string(),
+            },
+            // writes to flash and RAM are accepted, but the binary is read from the ELF file
+            ("M", _) | ("X", _) | ("G", _) => "OK".to_string(),
+            ("Z", bkpt) | ("z", bkpt) if bkpt.starts_with('0') || bkpt.starts_with('1') => {
+                match bkpt.split(',').nth(1).and_then(parse_hex_u32) {
+                    Some(address) if cmd.starts_with('Z') => {
+                        self.breakpoints.insert(address);
+                        "OK".to_string()
+                    }
+                    Some(address) => {
+                        self.breakpoints.remove(&address);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            ("?", "") => stop_reply(SIGTRAP),
+            ("!", "") => "OK".to_string(),
+            ("H", _) => "OK".to_string(),
+            _ => match cmd {
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                "qSymbol::" => "OK".to_string(),
+                cmd if cmd.starts_with("vKill") => "OK".to_string(),
+                // empty reply for unsupported commands
+                _ => String::new(),
+            },
+        }
+    }
+
+    /// Handles `monitor` commands.
+    ///
+    /// Only `rtt server start <port> <channel> [<greeting>]` has an effect, all other commands are accepted.
+    async fn monitor(&mut self, cmd: &str) -> String {
+        let args: Vec<&str> = cmd.split_whitespace().collect();
+
+        if let ["rtt", "server", "start", port, _channel, greeting @ ..] = args.as_slice() {
+            if greeting.len() > 1 {
+                return "E01".to_string();
+            }
+            let Ok(port) = port.parse::<u16>() else {
+                return "E01".to_string();
+            };
+
+            return match TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port))
+                .await
+            {
+                Ok(listener) => {
+                    let greeting = greeting.first().map(|greeting| greeting.to_string());
+                    self.rtt = Some(Rtt::Listening(AcceptTask::spawn(listener, greeting)));
+                    "OK".to_string()
+                }
+                Err(err) => {
+                    log::error!("Simulated target could not start RTT server. Cause: {err}");
+                    "E01".to_string()
+                }
+            };
+        }
+
+        "OK".to_string()
+    }
+
+    /// Continues the target.
+    ///
+    /// Returns the stop reply if the target stops at a breakpoint before `main`,
+    /// or `None` if the target runs the binary.
+    fn resume(&mut self) -> Option<String> {
+        if self.stage == Stage::Started {
+            return None;
+        }
+
+        self.stage = Stage::Started;
+        let bkpt = self
+            .breakpoints
+            .iter()
+            .find(|address| !self.target.is_hard_fault(**address))?;
+
+        self.registers[PC] = *bkpt;
+        Some(stop_reply(SIGTRAP))
+    }
+
+    fn start_replay(&mut self) -> Replay {
+        let stop = CancellationToken::new();
+        let replay_stop = stop.clone();
+        let rtt = self.rtt.take().unwrap_or(Rtt::Stopped);
+        let target = self.target.clone();
+        let start = self.replayed;
+
+        let task = tokio::spawn(async move {
+            let mut stream = match rtt {
+                Rtt::Connected(stream) => stream,
+                Rtt::Listening(mut accept) => {
+                    let accepted = tokio::select! {
+                        accepted = &mut accept.0 => accepted,
+                        _ = replay_stop.cancelled() => return (Rtt::Listening(accept), start),
+                    };
+                    match accepted {
+                        Ok(Ok(stream)) => stream,
+                        // RTT server failed => nothing to replay
+                        _ => return (Rtt::Stopped, start),
+                    }
+                }
+                // nothing to replay without RTT server => target stops right away
+                Rtt::Stopped => return (Rtt::Stopped, start),
+            };
+
+            let mut replayed = start;
+            for chunk in target.rtt_data[start..].chunks(REPLAY_CHUNK_SIZE) {
+                if replay_stop.is_cancelled() || stream.write_all(chunk).await.is_err() {
+                    break;
+                }
+                replayed += chunk.len();
+                tokio::time::sleep(REPLAY_CHUNK_DELAY).await;
+            }
+
+            (Rtt::Connected(stream), replayed)
+        });
+
+        Replay { stop, task }
+    }
+
+    fn finish_replay(
+        &mut self,
+        replayed: Result<(Rtt, usize), tokio::task::JoinError>,
+    ) -> std::io::Result<()> {
+        let (rtt, replayed) = replayed.map_err(std::io::Error::other)?;
+        self.rtt = Some(rtt);
+        self.replayed = replayed;
+        Ok(())
+    }
+}
+
+/// Reads the next packet or interrupt from GDB.
+///
+/// Acknowledgments are skipped, and `None` is returned once GDB closed the connection.
+async fn read_input(reader: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Option<Input>> {
+    loop {
+        let byte = match reader.read_u8().await {
+            Ok(byte) => byte,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        match byte {
+            0x03 => return Ok(Some(Input::Interrupt)),
+            b'$' => break,
+            _ => continue,
+        }
+    }
+
+    let mut packet = Vec::new();
+    let mut escaped = false;
+    loop {
+        let byte = reader.read_u8().await?;
+
+        if escaped {
+            packet.push(byte ^ 0x20);
+            escaped = false;
+        } else if byte == b'}' {
+            escaped = true;
+        } else if byte == b'#' {
+            break;
+        } else {
+            packet.push(byte);
+        }
+    }
+
+    // checksum is not verified, because TCP already ensures data integrity
+    let mut checksum = [0; 2];
+    reader.read_exact(&mut checksum).await?;
+
+    Ok(Some(Input::Packet(packet)))
+}
+
+async fn send_packet(writer: &mut OwnedWriteHalf, data: &str) -> std::io::Result<()> {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    writer
+        .write_all(format!("${data}#{checksum:02x}").as_bytes())
+        .await
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("T{signal:02x}thread:1;")
+}
+
+/// Returns the requested chunk of a `qXfer` object for a request in the form `<offset>,<length>`.
+fn xfer_chunk(object: &str, request: &str) -> Option<String> {
+    let (offset, length) = request.split_once(',')?;
+    let offset = usize::from_str_radix(offset, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+
+    let chunk = object.get(offset.min(object.len())..)?;
+    if chunk.len() <= length {
+        Some(format!("l{chunk}"))
+    } else {
+        Some(format!("m{}", &chunk[..length]))
+    }
+}
+
+/// Maps the GDB register number in the target description to the index of the register.
+fn register_index(regnum: &str) -> Option<usize> {
+    match usize::from_str_radix(regnum, 16).ok()? {
+        reg @ 0..=15 => Some(reg),
+        XPSR_REGNUM => Some(XPSR),
+        _ => None,
+    }
+}
+
+/// Parses a memory range in the form `<address>,<length>`.
+fn parse_range(range: &str) -> Option<(u32, usize)> {
+    let (address, length) = range.split_once(',')?;
+    Some((
+        parse_hex_u32(address)?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn parse_hex_u32(value: &str) -> Option<u32> {
+    u32::from_str_radix(value, 16).ok()
+}
+
+/// Encodes a register value in target byte order (little endian).
+fn hex_u32(value: u32) -> String {
+    hex_encode(&value.to_le_bytes())
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Target description of a Cortex-M core.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>arm</architecture>
+  <feature name="org.gnu.gdb.arm.m-profile">
+    <reg name="r0" bitsize="32" regnum="0"/>
+    <reg name="r1" bitsize="32"/>
+    <reg name="r2" bitsize="32"/>
+    <reg name="r3" bitsize="32"/>
+    <reg name="r4" bitsize="32"/>
+    <reg name="r5" bitsize="32"/>
+    <reg name="r6" bitsize="32"/>
+    <reg name="r7" bitsize="32"/>
+    <reg name="r8" bitsize="32"/>
+    <reg name="r9" bitsize="32"/>
+    <reg name="r10" bitsize="32"/>
+    <reg name="r11" bitsize="32"/>
+    <reg name="r12" bitsize="32"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="lr" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="xpsr" bitsize="32" regnum="25"/>
+  </feature>
+</target>
+"#;
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::{hex_encode, SimTarget};
+
+    /// Sends one packet like GDB does, and returns the reply.
+    async fn request(stream: &mut TcpStream, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        stream
+            .write_all(format!("${data}#{checksum:02x}").as_bytes())
+            .await
+            .unwrap();
+
+        let mut reply = Vec::new();
+        loop {
+            match stream.read_u8().await.unwrap() {
+                b'+' if reply.is_empty() => {}
+                b'$' => {}
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).await.unwrap();
+        stream.write_all(b"+").await.unwrap();
+
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replay_after_main() {
+        let rtt_data = std::fs::read(PathBuf::from("test_binaries/emb-runner-test.rtt")).unwrap();
+        let target = SimTarget::new(
+            &PathBuf::from("test_binaries/emb-runner-test"),
+            rtt_data.clone(),
+        )
+        .unwrap();
+        let server = target.serve().await.unwrap();
+        let mut gdb = TcpStream::connect(("127.0.0.1", server.gdb_port()))
+            .await
+            .unwrap();
+
+        // reserve a free port for the RTT server
+        let rtt_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let monitor = hex_encode(format!("rtt server start {rtt_port} 0").as_bytes());
+        assert_eq!(request(&mut gdb, &format!("qRcmd,{monitor}")).await, "OK");
+        let mut rtt = TcpStream::connect(("127.0.0.1", rtt_port)).await.unwrap();
+
+        assert_eq!(request(&mut gdb, "Z0,8000254,2").await, "OK");
+        assert_eq!(
+            request(&mut gdb, "c").await,
+            "T05thread:1;",
+            "Target did not stop at `main`."
+        );
+        assert_eq!(
+            request(&mut gdb, "pf").await,
+            "54020008",
+            "PC not at the breakpoint."
+        );
+
+        let stop = request(&mut gdb, "c").await;
+        let mut received = vec![0; rtt_data.len()];
+        rtt.read_exact(&mut received).await.unwrap();
+
+        assert_eq!(stop, "T05thread:1;", "Target did not stop after replay.");
+        assert_eq!(received, rtt_data, "RTT data not replayed.");
+    }
+}
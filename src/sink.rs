@@ -0,0 +1,213 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use defmt_json_schema::v1::JsonFrame;
+
+use crate::coverage::CoverageBuilder;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SinkError {
+    #[error("Could not write to file '{}'. Cause: {}", .0.display(), .1)]
+    Write(PathBuf, std::io::Error),
+}
+
+/// Receives decoded defmt frames as soon as they arrive.
+///
+/// Sinks must not keep all frames, because runs may last for hours.
+/// Frames are pushed from a blocking thread, so sinks may use blocking IO.
+pub trait FrameSink: Send {
+    /// Handles one decoded frame.
+    fn push(&mut self, frame: &JsonFrame) -> Result<(), SinkError>;
+
+    /// Called once after the last frame was pushed.
+    fn finish(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Writes frames as JSON lines to a log file.
+///
+/// The file is created when the sink is created, so logs of previous runs are removed even if no frame arrives.
+#[derive(Debug)]
+pub struct LogFileSink {
+    filepath: PathBuf,
+    writer: BufWriter<File>,
+    nr_frames: usize,
+}
+
+impl LogFileSink {
+    /// Creates the log file, truncating an existing one.
+    pub async fn create(filepath: PathBuf) -> Result<Self, SinkError> {
+        let file = match tokio::fs::File::create(&filepath).await {
+            Ok(file) => file.into_std().await,
+            Err(err) => return Err(SinkError::Write(filepath, err)),
+        };
+
+        Ok(Self {
+            filepath,
+            writer: BufWriter::new(file),
+            nr_frames: 0,
+        })
+    }
+
+    pub fn filepath(&self) -> &Path {
+        &self.filepath
+    }
+
+    /// Number of frames written to the log file.
+    pub fn nr_frames(&self) -> usize {
+        self.nr_frames
+    }
+
+    fn write_err(&self, err: std::io::Error) -> SinkError {
+        SinkError::Write(self.filepath.clone(), err)
+    }
+}
+
+impl FrameSink for LogFileSink {
+    fn push(&mut self, frame: &JsonFrame) -> Result<(), SinkError> {
+        let line = serde_json::to_string(frame).expect("DefmtFrame is valid JSON.");
+        let written = self
+            .writer
+            .write_all(line.as_bytes())
+            .and_then(|_| self.writer.write_all(b"\n"));
+        written.map_err(|err| self.write_err(err))?;
+
+        self.nr_frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        self.writer.flush().map_err(|err| self.write_err(err))
+    }
+}
+
+/// Prints frames with a log level using the host logger, and all other frames directly to stdout.
+#[derive(Debug, Default)]
+pub struct ConsoleSink;
+
+impl FrameSink for ConsoleSink {
+    fn push(&mut self, json_frame: &JsonFrame) -> Result<(), SinkError> {
+        let mod_path = if let Some(mod_path) = &json_frame.location.module_path {
+            if mod_path.modules.is_empty() {
+                Some(format!("{}::{}", mod_path.crate_name, mod_path.function))
+            } else {
+                Some(format!(
+                    "{}::{}::{}",
+                    mod_path.crate_name,
+                    mod_path.modules.join("::"),
+                    mod_path.function
+                ))
+            }
+        } else {
+            None
+        };
+
+        // use kv feature due to lifetime problems with arg
+        let val = Some([("msg", log::kv::Value::from_display(&json_frame.data))]);
+
+        match json_frame.level {
+            Some(level) => {
+                let log_record = log::RecordBuilder::new()
+                    .level(level)
+                    .file(json_frame.location.file.as_deref())
+                    .line(json_frame.location.line)
+                    .module_path(mod_path.as_deref())
+                    .target("embedded")
+                    .key_values(&val)
+                    .build();
+                log::logger().log(&log_record);
+            }
+            None => {
+                // mantra coverage logs not printed to remove clutter
+                if mantra_rust_macros::extract::extract_first_coverage(&json_frame.data).is_none() {
+                    println!("TARGET-PRINT | {}", json_frame.data);
+
+                    if log::Level::Trace <= log::STATIC_MAX_LEVEL
+                        && log::Level::Trace <= log::max_level()
+                    {
+                        #[allow(clippy::unnecessary_unwrap)]
+                        let location = if json_frame.location.file.is_some()
+                            && json_frame.location.line.is_some()
+                            && mod_path.is_some()
+                        {
+                            format!(
+                                "{} in {}:{}",
+                                mod_path.unwrap(),
+                                json_frame.location.file.as_ref().unwrap(),
+                                json_frame.location.line.unwrap(),
+                            )
+                        } else {
+                            "no-location info available".to_string()
+                        };
+
+                        println!("             | => {location}");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends frames to a receiver outside the run (e.g. to stream them to a remote client).
+pub type FrameSender = tokio::sync::mpsc::UnboundedSender<JsonFrame>;
+
+impl FrameSink for FrameSender {
+    fn push(&mut self, frame: &JsonFrame) -> Result<(), SinkError> {
+        // receiver may be gone, but the run must go on
+        let _ = self.send(frame.clone());
+        Ok(())
+    }
+}
+
+/// Built-in sinks every run writes to.
+#[derive(Debug)]
+pub struct RunSinks {
+    /// Writes all frames to `defmt.log`.
+    pub log_file: LogFileSink,
+    pub console: ConsoleSink,
+    /// Builds the test coverage while frames arrive.
+    pub coverage: CoverageBuilder,
+    /// Receives all frames, if set.
+    pub forward: Option<FrameSender>,
+}
+
+impl RunSinks {
+    /// Creates the sinks for a run storing its results in the given directory.
+    pub async fn create(output_dir: &Path) -> Result<Self, SinkError> {
+        Ok(Self {
+            log_file: LogFileSink::create(output_dir.join("defmt.log")).await?,
+            console: ConsoleSink,
+            coverage: CoverageBuilder::default(),
+            forward: None,
+        })
+    }
+
+    /// Additionally sends all frames to the given sender.
+    pub fn forward_to(mut self, sender: Option<FrameSender>) -> Self {
+        self.forward = sender;
+        self
+    }
+}
+
+impl FrameSink for RunSinks {
+    fn push(&mut self, frame: &JsonFrame) -> Result<(), SinkError> {
+        self.log_file.push(frame)?;
+        self.console.push(frame)?;
+        if let Some(forward) = &mut self.forward {
+            forward.push(frame)?;
+        }
+        self.coverage.push(frame)
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        self.log_file.finish()?;
+        self.console.finish()?;
+        self.coverage.finish()
+    }
+}